@@ -0,0 +1,82 @@
+use build_script::{output, BundleBuilder, OutputFormat};
+use image_format::Bundle;
+
+use clap::Parser;
+use std::path::PathBuf;
+use std::{error::Error, process};
+
+/// Pack module images into one bundle, indexed by the module names and versions
+#[derive(Parser, Debug)]
+struct Args {
+    /// images to bundle, named by the name and version stored in them
+    #[clap(required = true)]
+    images: Vec<PathBuf>,
+    /// where to write the bundle
+    #[clap(short, long)]
+    output: PathBuf,
+    /// names the array or symbols of the bundle, defaults to the file stem of the output
+    #[clap(short, long)]
+    name: Option<String>,
+    /// how to write the bundle: bin, rust (static array), c (header), ihex, srec or elf (object)
+    #[clap(long, default_value = "bin")]
+    format: String,
+    /// flash address the ihex and srec formats put the bundle at
+    #[clap(long, value_name = "ADDRESS", parse(try_from_str = output::parse_address))]
+    base: Option<u32>,
+    /// section holding the bundle in the elf format, defaults to .rodata.<NAME>
+    #[clap(long)]
+    section: Option<String>,
+    /// print the index of the bundle
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(err) = bundle(args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn bundle(args: Args) -> Result<(), Box<dyn Error>> {
+    let format = OutputFormat::from_name(&args.format, args.base, args.section)?;
+    let name = match args.name {
+        Some(name) => name,
+        None => args
+            .output
+            .file_stem()
+            .ok_or("no output file name")?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let mut builder = BundleBuilder::new();
+    for image in &args.images {
+        builder = builder.image_file(image)?;
+    }
+    let bundle = builder.build()?;
+    output::write(&args.output, &format, &name, &bundle)?;
+    if args.verbose {
+        let index = Bundle::decode(&bundle).map_err(|err| format!("{:?}", err))?;
+        println!(
+            "{:<20} {:<10} {:>8} {:>8} crc32",
+            "module", "version", "offset", "length"
+        );
+        for module in index.iter() {
+            let version = String::from_utf8_lossy(module.version);
+            println!(
+                "{:<20} {:<10} {:>#8x} {:>8} {:08x}",
+                String::from_utf8_lossy(module.name),
+                if version.is_empty() {
+                    "-".into()
+                } else {
+                    version
+                },
+                module.image.as_ptr() as usize - bundle.as_ptr() as usize,
+                module.image.len(),
+                module.crc32
+            );
+        }
+    }
+    Ok(())
+}
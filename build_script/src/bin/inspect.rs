@@ -0,0 +1,142 @@
+use build_script::inspect::{ImageInfo, InspectOptions};
+use build_script::utils::encryption::EncryptionKey;
+use build_script::SymbolMap;
+use image_format::{Bundle, BUNDLE_MAGIC};
+
+use clap::Parser;
+use std::path::PathBuf;
+use std::{error::Error, fs, process};
+
+/// Print what a module image built by build_script holds, or the index of a bundle
+#[derive(Parser, Debug)]
+struct Args {
+    /// the image or bundle to inspect
+    image: PathBuf,
+    /// inspect this module of a bundle rather than listing them
+    #[clap(long, value_name = "NAME")]
+    module: Option<String>,
+    /// symbol map naming the symbols of an image with hashed names,
+    /// defaults to <IMAGE>.map if there is one
+    #[clap(long)]
+    map: Option<PathBuf>,
+    /// AES-256 key to decrypt an encrypted image with, a file with the raw 32 bytes
+    #[clap(long, value_name = "KEY")]
+    key: Option<PathBuf>,
+    /// id the image was encrypted with --key under
+    #[clap(long, default_value_t = 0)]
+    key_id: u32,
+    /// print JSON instead of text
+    #[clap(long)]
+    json: bool,
+    /// leave out the disassembly of .text
+    #[clap(long)]
+    no_disassembly: bool,
+    /// llvm-objdump binary, used to disassemble .text
+    #[clap(long, default_value = "llvm-objdump")]
+    objdump: String,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(err) = inspect(args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// print the index of `bundle`
+fn list_bundle(bundle: &Bundle, json: bool) {
+    let modules = bundle.iter().map(|module| {
+        (
+            String::from_utf8_lossy(module.name),
+            String::from_utf8_lossy(module.version),
+            module,
+        )
+    });
+    if json {
+        let modules: Vec<_> = modules
+            .map(|(name, version, module)| {
+                serde_json::json!({
+                    "name": name,
+                    "version": version,
+                    "length": module.image.len(),
+                    "crc32": module.crc32,
+                    "crc_ok": module.check().is_ok(),
+                })
+            })
+            .collect();
+        println!("{:#}", serde_json::json!({ "modules": modules }));
+        return;
+    }
+    println!("bundle of {} modules", bundle.len());
+    for (name, version, module) in modules {
+        let version = if version.is_empty() {
+            "-".into()
+        } else {
+            version
+        };
+        let state = if module.check().is_ok() {
+            "ok"
+        } else {
+            "CORRUPTED"
+        };
+        println!(
+            "  {:<20} {:<10} {:>8} bytes  crc32 {:08x} {}",
+            name,
+            version,
+            module.image.len(),
+            module.crc32,
+            state
+        );
+    }
+}
+
+fn inspect(args: Args) -> Result<(), Box<dyn Error>> {
+    let mut image = fs::read(&args.image)?;
+    if image.get(..4) == Some(&BUNDLE_MAGIC.to_le_bytes()) {
+        let bundle = Bundle::decode(&image)
+            .map_err(|err| format!("{}: not a bundle: {:?}", args.image.display(), err))?;
+        let Some(name) = &args.module else {
+            list_bundle(&bundle, args.json);
+            return Ok(());
+        };
+        let module = bundle
+            .find(name)
+            .ok_or_else(|| format!("{}: no module {}", args.image.display(), name))?;
+        let module = module
+            .check()
+            .map_err(|err| format!("{}: module {}: {:?}", args.image.display(), name, err))?;
+        image = module.to_vec();
+    } else if args.module.is_some() {
+        return Err(format!("{} is not a bundle", args.image.display()).into());
+    }
+    let map = match args.map {
+        Some(map) => Some(map),
+        None => Some(args.image.with_extension("map")).filter(|map| map.exists()),
+    };
+    let options = InspectOptions {
+        symbol_map: map.map(SymbolMap::read).transpose()?,
+        key: args
+            .key
+            .map(|key| EncryptionKey::read(args.key_id, key))
+            .transpose()?,
+    };
+    let info = ImageInfo::parse(&image, &options)
+        .map_err(|err| format!("{}: {}", args.image.display(), err))?;
+    let disassembly = match (args.no_disassembly, &info.text) {
+        (false, Some(_)) => Some(info.disassemble(&args.objdump)?),
+        _ => None,
+    };
+    if args.json {
+        println!("{:#}", info.to_json(disassembly.as_deref()));
+        return Ok(());
+    }
+    print!("{}", info);
+    if let Some(lines) = disassembly {
+        println!("\ndisassembly of .text:");
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
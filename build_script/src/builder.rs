@@ -0,0 +1,204 @@
+//! Builder-style entry point, usable from a `build.rs`
+use crate::image::{self, ImageOptions, ModuleImage};
+use crate::toolchain::{self, Toolchain};
+use crate::utils::encryption::EncryptionKey;
+use crate::utils::readelf;
+use crate::utils::symbol_map::SymbolMap;
+use ed25519_dalek::SigningKey;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Build a module image from relocatable objects
+///
+/// ```no_run
+/// let image = build_script::ImageBuilder::new("app")
+///     .object("app.o")
+///     .out_dir("target/modules")
+///     .build()?;
+/// image.write("target/modules/app.bin")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ImageBuilder {
+    name: String,
+    objects: Vec<String>,
+    out_dir: PathBuf,
+    toolchain: Toolchain,
+    options: ImageOptions,
+    dependency_maps: Vec<PathBuf>,
+}
+
+impl ImageBuilder {
+    /// `name` is the module name, it also names the linked ELF
+    pub fn new(name: impl Into<String>) -> Self {
+        ImageBuilder {
+            name: name.into(),
+            objects: Vec::new(),
+            out_dir: PathBuf::from("."),
+            toolchain: Toolchain::default(),
+            options: ImageOptions::default(),
+            dependency_maps: Vec::new(),
+        }
+    }
+
+    /// add an object to link into the module
+    pub fn object(mut self, path: impl AsRef<Path>) -> Self {
+        self.objects
+            .push(path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    pub fn objects<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        for path in paths {
+            self = self.object(path);
+        }
+        self
+    }
+
+    /// directory for the intermediate files (trampolines, linked ELF), defaults to "."
+    pub fn out_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = dir.into();
+        self
+    }
+
+    pub fn linker_script(mut self, path: impl Into<String>) -> Self {
+        self.toolchain.linker_script = path.into();
+        self
+    }
+
+    pub fn clang(mut self, clang: impl Into<String>) -> Self {
+        self.toolchain.clang = clang.into();
+        self
+    }
+
+    pub fn ld_lld(mut self, ld_lld: impl Into<String>) -> Self {
+        self.toolchain.ld_lld = ld_lld.into();
+        self
+    }
+
+    /// echo the toolchain output
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.toolchain.verbose = verbose;
+        self
+    }
+
+    /// skip relocations of type `r_type` when checking that dl-lib can apply every relocation,
+    /// for types known to be harmless in this module, see `relocations::parse_relocation_type`
+    pub fn allow_relocation(mut self, r_type: u32) -> Self {
+        self.options.allowed_relocations.push(r_type);
+        self
+    }
+
+    /// store the 32-bit hash of each symbol name instead of the name,
+    /// `ModuleImage::symbol_map` keeps the names on the host
+    pub fn hashed_names(mut self, hashed_names: bool) -> Self {
+        self.options.hashed_names = hashed_names;
+        self
+    }
+
+    /// store .text and .data LZ4-compressed, dl-lib decompresses them into RAM
+    /// and runs .text from there instead of in place
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.options.compress = compress;
+        self
+    }
+
+    /// encrypt .text and .data with `key`, dl-lib decrypts them into RAM
+    /// and runs .text from there instead of in place
+    pub fn encrypt(mut self, key: EncryptionKey) -> Self {
+        self.options.encryption_key = Some(key);
+        self
+    }
+
+    /// sign the image with `key`, dl-lib checks the signature against its trusted keys
+    pub fn sign(mut self, key: SigningKey) -> Self {
+        self.options.signing_key = Some(key);
+        self
+    }
+
+    /// enter exported functions through trampolines in .text that switch R9 to the module's
+    /// static base, instead of the PLT dl-lib builds in RAM, dl-lib then runs .text from RAM.
+    /// Exported functions must not take arguments on the stack, more than four words of
+    /// arguments or variadic: the trampoline pushes two words in front of them, so they
+    /// would read the wrong ones. This isn't detected, the object files don't say.
+    pub fn trampolines(mut self, trampolines: bool) -> Self {
+        self.options.trampolines = trampolines;
+        self
+    }
+
+    /// module version, stored in the image's metadata and listed in bundle indexes
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.options.version = Some(version.into());
+        self
+    }
+
+    /// symbol map of a module this one will be resolved against,
+    /// the build fails if one of its exports has the same hash as a symbol of this module
+    pub fn dependency_map(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dependency_maps.push(path.into());
+        self
+    }
+
+    /// path of the linked ELF the image is made from
+    pub fn elf_path(&self) -> PathBuf {
+        self.out_dir.join(format!("{}.elf", self.name))
+    }
+
+    /// link the objects and convert the result into an image
+    pub fn build(&self) -> Result<ModuleImage, Box<dyn Error>> {
+        if self.objects.is_empty() {
+            return Err(format!("no objects given for module {}", self.name).into());
+        }
+        let dependencies = self
+            .dependency_maps
+            .iter()
+            .map(SymbolMap::read)
+            .collect::<Result<Vec<_>, _>>()?;
+        let elf_path = self.elf_path().to_string_lossy().into_owned();
+
+        readelf::check_duplicate_definitions(&self.objects)?;
+        readelf::check_static_base_relocations(&self.objects)?;
+
+        // a weak function may be defined by several objects, but is exported once
+        let mut glb_funcs: Vec<String> = Vec::new();
+        for path in &self.objects {
+            for func in readelf::get_pub_funcs(path)? {
+                if !glb_funcs.contains(&func) {
+                    glb_funcs.push(func);
+                }
+            }
+        }
+
+        let mut linker_input_paths = self.objects.clone();
+        // exported functions switch R9 themselves instead of going through dl-lib's PLT
+        if self.options.trampolines && !glb_funcs.is_empty() {
+            linker_input_paths.push(toolchain::compile_trampolines(
+                &self.toolchain,
+                &glb_funcs,
+                &self.name,
+                &self.out_dir,
+            )?);
+        }
+        // calls into other modules that don't go through the GOT get a veneer that does
+        let imports = readelf::get_called_imports(&self.objects)?;
+        if !imports.is_empty() {
+            linker_input_paths.push(toolchain::compile_veneers(
+                &self.toolchain,
+                &imports,
+                &self.name,
+                &self.out_dir,
+            )?);
+        }
+
+        toolchain::link_objects(&self.toolchain, &linker_input_paths, &elf_path)?;
+
+        let image = image::make_image(&self.name, &elf_path, glb_funcs, &self.options)?;
+        // dl-lib finds symbols by the hash of their name, named images included
+        image.symbol_map().check_collisions(&dependencies)?;
+        Ok(image)
+    }
+}
@@ -0,0 +1,107 @@
+//! Several module images packed into one bundle with an index, see `image_format::Bundle`
+use crate::image::ModuleImage;
+use image_format::{encode_bundle, Metadata, Prefix, Records, RECORD_METADATA};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// A module as it goes into the bundle
+#[derive(Debug, Clone)]
+struct BundledModule {
+    name: String,
+    version: String,
+    image: Vec<u8>,
+}
+
+/// Pack module images into a bundle, dl-lib loads them from it by name
+///
+/// ```no_run
+/// let bundle = build_script::BundleBuilder::new()
+///     .image_file("target/modules/app.bin")?
+///     .image_file("target/modules/lib.bin")?
+///     .build()?;
+/// std::fs::write("target/modules/modules.bundle", bundle)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BundleBuilder {
+    modules: Vec<BundledModule>,
+}
+
+impl BundleBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add `image` under `name` and `version`, modules keep the order they are added in
+    pub fn image(
+        mut self,
+        name: impl Into<String>,
+        version: impl Into<String>,
+        image: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.modules.push(BundledModule {
+            name: name.into(),
+            version: version.into(),
+            image: image.into(),
+        });
+        self
+    }
+
+    /// add an image built by `ImageBuilder`, under its name and version
+    pub fn module(self, image: &ModuleImage) -> Self {
+        let version = image.version.clone().unwrap_or_default();
+        self.image(&image.name, version, image.bytes.clone())
+    }
+
+    /// add the image in `path`, under the name and version in its metadata
+    pub fn image_file(self, path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let image = fs::read(path)?;
+        let (name, version) =
+            image_name(&image).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(self.image(name, version, image))
+    }
+
+    /// the bundle, refused if two modules have the same name or an image doesn't decode
+    pub fn build(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        for (i, module) in self.modules.iter().enumerate() {
+            if module.name.is_empty() || module.name.contains('\0') {
+                return Err(format!("`{}` can't name a bundled module", module.name).into());
+            }
+            if module.version.contains('\0') {
+                return Err(format!("version of module {} contains NUL", module.name).into());
+            }
+            if self.modules[..i].iter().any(|m| m.name == module.name) {
+                return Err(format!("module {} is bundled twice", module.name).into());
+            }
+            Prefix::decode(&module.image).map_err(|err| {
+                format!("module {} is not a module image: {:?}", module.name, err)
+            })?;
+        }
+        let modules: Vec<_> = self
+            .modules
+            .iter()
+            .map(|m| (m.name.as_str(), m.version.as_str(), m.image.as_slice()))
+            .collect();
+        Ok(encode_bundle(&modules))
+    }
+}
+
+/// Module name and version stored in the metadata record of `image`,
+/// the version is empty if the image has none
+pub fn image_name(image: &[u8]) -> Result<(String, String), Box<dyn Error>> {
+    let (_, records) =
+        Prefix::decode(image).map_err(|err| format!("not a module image: {:?}", err))?;
+    let metadata = Records::new(records)
+        .filter_map(Result::ok)
+        .find(|record| record.tag == RECORD_METADATA)
+        .map(|record| Metadata::new(record.payload))
+        .ok_or("image has no metadata record")?;
+    let value = |key| String::from_utf8(metadata.get(key).unwrap_or_default().to_vec());
+    let name = value("name")?;
+    if name.is_empty() {
+        return Err("image has no module name".into());
+    }
+    Ok((name, value("version")?))
+}
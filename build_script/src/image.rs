@@ -0,0 +1,629 @@
+//! Conversion of a linked module into the image format read by dl-lib
+use crate::output::{self, OutputFormat};
+use crate::toolchain;
+use crate::utils::encryption::{self, EncryptionKey};
+use crate::utils::error::BuildError;
+use crate::utils::layout::{Region, SectionLayout};
+use crate::utils::relocations::{Relocation, RelocationType};
+use crate::utils::symbol_map::SymbolMap;
+use crate::utils::symbols::{ModuleSymbol, SymbolType};
+use crate::utils::{literals, lz4, relocations, signing, symbols};
+use ed25519_dalek::SigningKey;
+use image_format::{
+    encode_image, encode_table, hash_table, name_bytes, push_record, DataReloc, Entry, GotReloc,
+    Layout, Metadata, RelocTarget, SymbolEntry, SymbolKind, Symbols, Trampoline,
+};
+use object::{elf, Object, ObjectSection, ObjectSymbol, SectionIndex};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::{error::Error, fs};
+
+/// How `make_image` turns the linked module into an image
+#[derive(Debug, Clone, Default)]
+pub struct ImageOptions {
+    /// relocation types accepted even though dl-lib can't apply them
+    pub allowed_relocations: Vec<u32>,
+    /// store the hash of each symbol name instead of the name
+    pub hashed_names: bool,
+    /// store .text and .data LZ4-compressed, dl-lib then runs .text from RAM
+    pub compress: bool,
+    /// encrypt .text and .data with this key, dl-lib then runs .text from RAM
+    pub encryption_key: Option<EncryptionKey>,
+    /// close the image with an Ed25519 signature record made with this key
+    pub signing_key: Option<SigningKey>,
+    /// module version stored in the metadata record, bundles list it in their index
+    pub version: Option<String>,
+    /// exported functions are entered through the trampolines linked into .text,
+    /// see `toolchain::compile_trampolines`, dl-lib then runs .text from RAM
+    pub trampolines: bool,
+}
+
+/// Sizes in bytes of the parts of an image, and of what dl-lib allocates for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageSizes {
+    pub text: usize,
+    pub data: usize,
+    /// .text and .data as stored in the image, smaller than text and data if compressed
+    pub stored_text: usize,
+    pub stored_data: usize,
+    pub bss: usize,
+    pub got: usize,
+    /// symbol entries and flat symbol names
+    pub symt: usize,
+    /// hash table over the exported symbols
+    pub hash: usize,
+    /// the whole image, prefix and signature record included
+    pub image: usize,
+}
+
+/// A module image together with what it exports and imports
+#[derive(Debug, Clone)]
+pub struct ModuleImage {
+    pub name: String,
+    pub version: Option<String>,
+    /// the image as dl-lib expects it
+    pub bytes: Vec<u8>,
+    /// symbols defined by the module and visible to other modules
+    pub exports: Vec<String>,
+    /// symbols the module expects another module to define
+    pub imports: Vec<String>,
+    pub sizes: ImageSizes,
+}
+
+impl ModuleImage {
+    /// write the image bytes to `path`
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        fs::write(path, &self.bytes)?;
+        Ok(())
+    }
+
+    /// write the image to `path` in `format`, arrays and symbols are named after the module
+    pub fn write_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: &OutputFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        output::write(path, format, &self.name, &self.bytes)
+    }
+
+    /// hashes and names of the exported and imported symbols, see `SymbolMap`
+    pub fn symbol_map(&self) -> SymbolMap {
+        SymbolMap::new(&self.name, &self.exports, &self.imports)
+    }
+}
+
+/// For a given object file, and its public functions,
+/// generate a binary image that can be parsed by dl-lib
+/// The image is a prefix followed by records, see the `image-format` crate for the tags
+/// and how each record is encoded, numbers have width=4 and are in little-endian order
+///
+/// magic, format version, arch/ABI tag, image length, crc32 of everything after the crc field
+/// then one record after the other: tag, payload length, payload padded to a multiple of 4
+///
+/// layout: code section length, data section length, bss section length,
+///     GOT length (R9 points at the GOT, .data and .bss follow it),
+///     flags (IMAGE_FLAG_HASHED_NAMES, IMAGE_FLAG_COMPRESSED)
+/// metadata: name=<module>, version=<version> if given
+/// encryption, if encrypted: key id, nonce, tag, see `utils::encryption`
+/// text: code section, LZ4-compressed if IMAGE_FLAG_COMPRESSED
+/// data: data section, LZ4-compressed if IMAGE_FLAG_COMPRESSED
+///     code and data section encrypted as one if there is an encryption record
+/// symbols: number of symbols, then
+///     symbol1 index in flat symbol names, symbol1 address
+///     symbol2 index in flat symbol names, symbol2 address
+///     ...
+///     flat symbol names = symbol1.name 0 symbol2.name 0 ...
+///     or with hashed names = hash(symbol1.name) hash(symbol2.name) ...
+/// relocs: relocation table (functions)
+///     reloc1 offset, reloc1 index in symbol table
+///     ...
+/// exports: func1's index in symbol table, func2's index in symbol table ...
+/// data relocs: data relocation table (pointers in .data)
+///     reloc1 offset in .data, reloc1 target kind << 28 | index in symbol table
+///     ...
+/// symbol hash: hash table over the exported symbols, see `image_format::SymbolHash`
+/// trampolines, if built with them: trampoline1 offset in .text, function1's index in symbol table
+///     ...
+/// init array, if there are constructors: constructor1 offset in .text, constructor2 offset ...
+/// fini array, if there are destructors: destructor1 offset in .text, ... in the order they run
+/// signature, if signed: public key, signature of the records before it, see `utils::signing`
+///
+pub fn make_image(
+    name: &str,
+    obj: &String,
+    glb_funcs: Vec<String>,
+    options: &ImageOptions,
+) -> Result<ModuleImage, Box<dyn Error>> {
+    let hashed_names = options.hashed_names;
+    let bin_data = fs::read(obj)?;
+    let obj_file = object::File::parse(&*bin_data)?;
+    let layout = SectionLayout::new(&obj_file)?;
+    // parsed once, the checks and tables below each take the relocations they need
+    let relocs = relocations::get_relocations(&bin_data)?;
+    check_relocations(&obj_file, &layout, &relocs, &options.allowed_relocations)?;
+    check_static_base_relocations(&layout, &relocs)?;
+    let code_section = &layout.text;
+    let mut data_section = layout.data.clone();
+    // .bss is NOBITS, only its size goes into the image, dl-lib zeroes it right after .data
+    let bss_len = layout.l_bss;
+    let filtered_symbols: Vec<_> = obj_file
+        .symbols()
+        .filter(|s| {
+            let name = s.name().unwrap();
+            !name.is_empty() && !name.starts_with("$t") && !name.starts_with("$d")
+        })
+        .collect();
+
+    // ordered by ELF symbol index, so the symbol table and everything indexing it
+    // come out the same for the same input
+    let mut symbol_by_index: BTreeMap<usize, ModuleSymbol> = BTreeMap::new();
+
+    // get symbol type (Exported, External, Local, None), section index, and address
+    for symbol in filtered_symbols {
+        if let Some(symbol_type) = symbols::get_symbol_type(&symbol) {
+            // defined symbols must be in .text or .data/.bss, e.g. absolute ones are left out
+            let placed = symbol
+                .section_index()
+                .and_then(|index| layout.offset(index, symbol.address()))
+                .is_some();
+            if symbol_type != SymbolType::External && !placed {
+                continue;
+            }
+            let name = symbol.name().unwrap();
+            // veneers import a function under a prefixed name, the loader looks up the function
+            let name = match symbol_type {
+                SymbolType::External => name.strip_prefix(literals::IMPORT_PREFIX).unwrap_or(name),
+                _ => name,
+            };
+            symbol_by_index.insert(
+                symbol.index().0,
+                ModuleSymbol {
+                    name: String::from(name),
+                    symbol_type,
+                    section: symbol.section_index(),
+                    address: symbol.address(),
+                },
+            );
+        }
+    }
+
+    let vec_relocations: Vec<_> = relocations::get_known_relocations(&relocs).collect();
+    let reloc_symbols: HashSet<_> = vec_relocations.iter().map(|var| var.sym_index).collect();
+
+    let mut sym_indices: Vec<usize> = Vec::new();
+    // Symbol Table: process names
+    for (k, v) in &symbol_by_index {
+        // exclude unused local symbol
+        // only used local symbols and external/exported symbols needs further processing
+        if reloc_symbols.contains(k) {
+            sym_indices.push(*k);
+        } else if let SymbolType::External | SymbolType::Exported = v.symbol_type {
+            sym_indices.push(*k);
+        }
+    }
+
+    // only exported and external symbols are named, so the names are unique
+    // even if several objects had local symbols of the same name
+    let name_entry = |symbol: &ModuleSymbol| match symbol.symbol_type {
+        SymbolType::External | SymbolType::Exported => name_bytes(&symbol.name, hashed_names),
+        SymbolType::Local => Vec::new(),
+    };
+    let flat_sym_names: Vec<_> = sym_indices
+        .iter()
+        .flat_map(|idx| name_entry(&symbol_by_index[idx]))
+        .collect();
+    let sym_table_len = sym_indices.len() * 8 + flat_sym_names.len();
+
+    let sym_table_idx: HashMap<usize, u32> = sym_indices
+        .iter()
+        .enumerate()
+        .map(|(idx, elf_idx)| (*elf_idx, idx as u32))
+        .collect();
+    let exported_idx: HashMap<&str, u32> = sym_indices
+        .iter()
+        .enumerate()
+        .filter(|(_, elf_idx)| {
+            matches!(symbol_by_index[*elf_idx].symbol_type, SymbolType::Exported)
+        })
+        .map(|(idx, elf_idx)| (symbol_by_index[elf_idx].name.as_str(), idx as u32))
+        .collect();
+    let hash_table = hash_table(
+        &sym_indices
+            .iter()
+            .enumerate()
+            .filter(|(_, elf_idx)| {
+                matches!(symbol_by_index[*elf_idx].symbol_type, SymbolType::Exported)
+            })
+            .map(|(idx, elf_idx)| (idx as u32, symbol_by_index[elf_idx].name.as_str()))
+            .collect::<Vec<_>>(),
+    );
+
+    let l_got = layout.l_got;
+    let data_relocs = data_relocations(
+        &layout,
+        &relocs,
+        &mut data_section,
+        &symbol_by_index,
+        &sym_table_idx,
+    )?;
+    let mut flags = 0;
+    if hashed_names {
+        flags |= image_format::IMAGE_FLAG_HASHED_NAMES;
+    }
+    let (stored_text, stored_data) = if options.compress {
+        flags |= image_format::IMAGE_FLAG_COMPRESSED;
+        (lz4::compress(code_section), lz4::compress(&data_section))
+    } else {
+        (code_section.clone(), data_section.clone())
+    };
+    let layout_record = Layout {
+        text: code_section.len() as u32,
+        data: data_section.len() as u32,
+        bss: bss_len as u32,
+        got: l_got as u32,
+        flags,
+    }
+    .to_bytes();
+
+    let mut image: Vec<u8> = Vec::new();
+    push_record(&mut image, image_format::RECORD_LAYOUT, &layout_record);
+    let mut metadata = vec![("name", name)];
+    if let Some(version) = &options.version {
+        metadata.push(("version", version));
+    }
+    push_record(
+        &mut image,
+        image_format::RECORD_METADATA,
+        &Metadata::encode(&metadata),
+    );
+    let mut payload = [stored_text.as_slice(), &stored_data].concat();
+    if let Some(key) = &options.encryption_key {
+        let encryption = encryption::encrypt(key, &layout_record, &mut payload);
+        push_record(
+            &mut image,
+            image_format::RECORD_ENCRYPTION,
+            &encryption.to_bytes(),
+        );
+    }
+    let (text_record, data_record) = payload.split_at(stored_text.len());
+    push_record(&mut image, image_format::RECORD_TEXT, text_record);
+    push_record(&mut image, image_format::RECORD_DATA, data_record);
+
+    let mut flat_sym_names_len = 0;
+    // Symbol table: number of symbols, entries, flat names
+    let symbol_entries: Vec<SymbolEntry> = sym_indices
+        .iter()
+        .map(|idx| {
+            let symbol = &symbol_by_index[idx];
+            let in_text =
+                symbol.section.and_then(|index| layout.region(index)) == Some(Region::Text);
+            // if its a variable, address equals its offset in .data, or .data length + its offset in .bss
+            // if its a function, address equals its entry, 0 for external symbols
+            let address = match (symbol.symbol_type, symbol.section) {
+                (SymbolType::External, _) | (_, None) => 0,
+                // .bss is laid out right after .data at load time
+                (_, Some(index)) => layout.offset(index, symbol.address).unwrap() as u32,
+            };
+            let entry = SymbolEntry {
+                kind: match symbol.symbol_type {
+                    SymbolType::Local => SymbolKind::Local,
+                    SymbolType::Exported => SymbolKind::Exported,
+                    SymbolType::External => SymbolKind::External,
+                },
+                in_text,
+                name_offset: flat_sym_names_len as u32,
+                address,
+            };
+            flat_sym_names_len += name_entry(symbol).len();
+            entry
+        })
+        .collect();
+    push_record(
+        &mut image,
+        image_format::RECORD_SYMBOLS,
+        &Symbols::encode(&symbol_entries, &flat_sym_names),
+    );
+
+    // Write Relocation table
+    let got_relocs = vec_relocations
+        .iter()
+        .map(|reloc| {
+            let symbol =
+                sym_table_idx
+                    .get(&reloc.sym_index)
+                    .ok_or_else(|| BuildError::UnplacedSymbol {
+                        symbol: reloc.name.clone(),
+                    })?;
+            // address to .word
+            Ok(GotReloc {
+                offset: reloc.r_offset,
+                symbol: *symbol,
+            })
+        })
+        .collect::<Result<Vec<_>, BuildError>>()?;
+    push_record(
+        &mut image,
+        image_format::RECORD_RELOCS,
+        &encode_table(&got_relocs),
+    );
+
+    // Write every global function's index
+    let exports: Vec<u32> = glb_funcs
+        .iter()
+        .map(|name| export_index(&exported_idx, name))
+        .collect::<Result<Vec<_>, BuildError>>()?;
+    push_record(
+        &mut image,
+        image_format::RECORD_EXPORTS,
+        &encode_table(&exports),
+    );
+
+    // Write Data relocation table
+    push_record(
+        &mut image,
+        image_format::RECORD_DATA_RELOCS,
+        &encode_table(&data_relocs),
+    );
+    push_record(&mut image, image_format::RECORD_SYMBOL_HASH, &hash_table);
+
+    // Write where the trampoline of each global function starts
+    if options.trampolines {
+        let trampolines = trampolines(&obj_file, &layout, &glb_funcs, &exported_idx)?;
+        push_record(
+            &mut image,
+            image_format::RECORD_TRAMPOLINES,
+            &encode_table(&trampolines),
+        );
+    }
+
+    // Write the constructors and destructors in the order dl-lib runs them,
+    // the entries of .fini_array run last to first
+    let constructors = layout.function_array(&obj_file, elf::SHT_INIT_ARRAY)?;
+    let mut destructors = layout.function_array(&obj_file, elf::SHT_FINI_ARRAY)?;
+    destructors.reverse();
+    for (tag, functions) in [
+        (image_format::RECORD_INIT_ARRAY, &constructors),
+        (image_format::RECORD_FINI_ARRAY, &destructors),
+    ] {
+        if !functions.is_empty() {
+            push_record(&mut image, tag, &encode_table(functions));
+        }
+    }
+
+    if let Some(key) = &options.signing_key {
+        let signature = signing::sign(&image, key);
+        push_record(
+            &mut image,
+            image_format::RECORD_SIGNATURE,
+            &signature.to_bytes(),
+        );
+    }
+    let names_of = |wanted: SymbolType| {
+        sym_indices
+            .iter()
+            .map(|idx| &symbol_by_index[idx])
+            .filter(|symbol| symbol.symbol_type == wanted)
+            .map(|symbol| symbol.name.clone())
+            .collect::<Vec<_>>()
+    };
+    let bytes = encode_image(&image);
+    Ok(ModuleImage {
+        name: String::from(name),
+        version: options.version.clone(),
+        exports: names_of(SymbolType::Exported),
+        imports: names_of(SymbolType::External),
+        sizes: ImageSizes {
+            text: code_section.len(),
+            data: data_section.len(),
+            stored_text: stored_text.len(),
+            stored_data: stored_data.len(),
+            bss: bss_len,
+            got: l_got,
+            symt: sym_table_len,
+            hash: hash_table.len(),
+            image: bytes.len(),
+        },
+        bytes,
+    })
+}
+
+/// Check that dl-lib can apply every relocation of the linked module,
+/// types in `allowed` are skipped
+///
+/// Relocations the linker resolved within .text stay valid wherever the module is placed,
+/// the others must be one the image carries over to the loader (GOT, data pointers) or
+/// R9-relative.
+fn check_relocations(
+    obj_file: &object::File,
+    layout: &SectionLayout,
+    relocs: &[Relocation],
+    allowed: &[u32],
+) -> Result<(), Box<dyn Error>> {
+    for reloc in relocs {
+        if allowed.contains(&reloc.raw_type()) {
+            continue;
+        }
+        let section = obj_file.section_by_index(SectionIndex(reloc.section))?;
+        // not loaded, e.g. debug info
+        let Some(region) = layout.region(section.index()) else {
+            continue;
+        };
+        let target = SectionIndex(reloc.sym_section);
+        let reason = match (&reloc.r_type, region) {
+            (RelocationType::NONE, _) => continue,
+            (RelocationType::GOT32 | RelocationType::SBREL, Region::Text) => continue,
+            (RelocationType::ABS32, Region::Data) => continue,
+            (RelocationType::CALL | RelocationType::PCREL, Region::Text) => {
+                if layout.region(target) == Some(Region::Text) {
+                    continue;
+                }
+                "its target isn't in .text"
+            }
+            (RelocationType::ABS32, Region::Text) => {
+                "absolute address in .text, which is executed in place"
+            }
+            (RelocationType::UNKNOWN, _) => "dl-lib can't apply this type",
+            _ => "dl-lib can't apply this type in this section",
+        };
+        let offset = reloc.r_offset as u64 - section.address();
+        let function = obj_file
+            .symbols()
+            .filter(|sym| {
+                sym.kind() == object::SymbolKind::Text
+                    && sym.section_index() == Some(section.index())
+            })
+            .find(|sym| {
+                let start = sym.address() & !1;
+                start <= reloc.r_offset as u64 && (reloc.r_offset as u64) < start + sym.size()
+            })
+            .and_then(|sym| sym.name().ok().map(String::from));
+        let symbol = match reloc.name.as_str() {
+            // section symbol
+            "" => obj_file
+                .section_by_index(target)
+                .and_then(|section| section.name().map(String::from))
+                .unwrap_or_default(),
+            name => String::from(name),
+        };
+        return Err(Box::new(BuildError::UnsupportedRelocation {
+            r_type: relocations::relocation_name(reloc.raw_type()),
+            section: String::from(section.name()?),
+            offset,
+            function,
+            symbol,
+            reason,
+        }));
+    }
+    Ok(())
+}
+
+/// Check that everything addressed relative to R9 lives in the GOT, .data or .bss
+///
+/// lld resolves MOVW/MOVT_BREL and SBREL32 against the first section of the RW segment,
+/// the loader allocates the GOT, .data and .bss as one block with the same layout and
+/// points R9 at its start, so the immediates computed at link time stay valid.
+fn check_static_base_relocations(
+    layout: &SectionLayout,
+    relocs: &[Relocation],
+) -> Result<(), Box<dyn Error>> {
+    for reloc in relocations::get_sbrel_relocations(relocs) {
+        if matches!(
+            layout.region(SectionIndex(reloc.sym_section)),
+            None | Some(Region::Text)
+        ) {
+            return Err(Box::new(BuildError::BadStaticBaseRelocation {
+                symbol: reloc.name.clone(),
+                offset: reloc.r_offset as u64,
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// Find the trampoline of every global function, in the order of `glb_funcs`
+///
+/// The trampolines are hidden, so they are local symbols of the linked module
+/// and only found by name, see `toolchain::compile_trampolines`.
+fn trampolines(
+    obj_file: &object::File,
+    layout: &SectionLayout,
+    glb_funcs: &[String],
+    exported_idx: &HashMap<&str, u32>,
+) -> Result<Vec<Trampoline>, Box<dyn Error>> {
+    let offsets: HashMap<String, u64> = obj_file
+        .symbols()
+        .filter_map(|symbol| {
+            let name = symbol.name().ok()?;
+            let index = symbol.section_index()?;
+            if !name.starts_with(literals::TRAMPOLINE_PREFIX)
+                || layout.region(index) != Some(Region::Text)
+            {
+                return None;
+            }
+            // the thumb bit isn't part of the offset
+            let offset = layout.offset(index, symbol.address() & !1)?;
+            Some((String::from(name), offset))
+        })
+        .collect();
+    glb_funcs
+        .iter()
+        .map(|func| {
+            let offset = offsets
+                .get(&toolchain::trampoline_name(func))
+                .ok_or_else(|| BuildError::MissingTrampoline {
+                    function: func.clone(),
+                })?;
+            Ok(Trampoline {
+                offset: *offset as u32,
+                symbol: export_index(exported_idx, func)?,
+            })
+        })
+        .collect()
+}
+
+/// Index of the global function `name` in the symbol table
+fn export_index(exported_idx: &HashMap<&str, u32>, name: &str) -> Result<u32, BuildError> {
+    exported_idx
+        .get(name)
+        .copied()
+        .ok_or_else(|| BuildError::MissingExport {
+            function: String::from(name),
+        })
+}
+
+/// Find the pointers stored in .data and turn each into a data relocation entry
+///
+/// The word in `data_section` is rewritten to the offset of its target in .text or .data
+/// (.bss following .data), or to the addend for an external symbol, dl-lib adds the
+/// runtime base or the symbol's address when resolving the module.
+fn data_relocations(
+    layout: &SectionLayout,
+    relocs: &[Relocation],
+    data_section: &mut [u8],
+    symbol_by_index: &BTreeMap<usize, ModuleSymbol>,
+    sym_table_idx: &HashMap<usize, u32>,
+) -> Result<Vec<DataReloc>, Box<dyn Error>> {
+    let mut data_relocs = Vec::new();
+    let sections: Vec<_> = layout.sections(Region::Data).collect();
+    for reloc in sections
+        .iter()
+        .flat_map(|section| relocations::get_abs_relocations(relocs, section.0))
+    {
+        let offset = reloc.r_offset as u64 - layout.data_start();
+        let at = offset as usize;
+        let word = u32::from_le_bytes(data_section[at..at + 4].try_into()?);
+        let target = SectionIndex(reloc.sym_section);
+        // a pointer before its target's section is fine, hence the wrapping arithmetic
+        let (target, value) = match layout.region(target) {
+            Some(Region::Text) => (
+                RelocTarget::Text,
+                layout.offset(target, word as u64).unwrap() as u32,
+            ),
+            Some(Region::Data | Region::Bss) => (
+                RelocTarget::Data,
+                layout.offset(target, word as u64).unwrap() as u32,
+            ),
+            // unresolved at link time, the word only holds the addend
+            None if target == SectionIndex(0)
+                && matches!(
+                    symbol_by_index.get(&reloc.sym_index),
+                    Some(symbol) if symbol.symbol_type == SymbolType::External
+                ) =>
+            {
+                (RelocTarget::Symbol(sym_table_idx[&reloc.sym_index]), word)
+            }
+            _ => {
+                return Err(Box::new(BuildError::UnsupportedDataPointer {
+                    symbol: reloc.name.clone(),
+                    offset,
+                }))
+            }
+        };
+        data_section[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        data_relocs.push(DataReloc {
+            offset: offset as u32,
+            target,
+        });
+    }
+    Ok(data_relocs)
+}
@@ -0,0 +1,782 @@
+//! Host-side view of a module image, what the `inspect` binary prints
+//!
+//! Decodes the image the way dl-lib does, with the `image-format` crate, and resolves
+//! what dl-lib only knows by index or hash: symbol names (from the image or a symbol map),
+//! the GOT slot each relocation fills, and the instructions around it.
+use crate::toolchain;
+use crate::utils::elf::{self, ElfSection, ElfSymbol, ElfSymbolKind};
+use crate::utils::encryption::{self, EncryptionKey};
+use crate::utils::lz4;
+use crate::utils::symbol_map::SymbolMap;
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use image_format::{
+    DataReloc, Encryption, Entry, GotReloc, Layout, Metadata, Prefix, RelocTarget, Signature,
+    SymbolEntry, SymbolHash, SymbolKind, Symbols, Table, Trampoline, IMAGE_FLAG_COMPRESSED,
+    IMAGE_FLAG_HASHED_NAMES, IMAGE_PREFIX_LEN, TRAMPOLINE_STATIC_BASE,
+};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::process::Command;
+
+/// Where the names and keys `ImageInfo::parse` can't take from the image come from
+#[derive(Debug, Default)]
+pub struct InspectOptions {
+    /// names of the symbols of an image with hashed names
+    pub symbol_map: Option<SymbolMap>,
+    /// decrypts .text and .data of an encrypted image
+    pub key: Option<EncryptionKey>,
+}
+
+/// One record of the image
+#[derive(Debug, Clone)]
+pub struct RecordInfo {
+    pub tag: u32,
+    /// offset of the record header in the image
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// An entry of the symbol table
+#[derive(Debug, Clone)]
+pub struct SymbolInfo {
+    pub entry: SymbolEntry,
+    /// hash dl-lib identifies the symbol by, None for local symbols
+    pub hash: Option<u32>,
+    /// None for local symbols and hashed names missing from the symbol map
+    pub name: Option<String>,
+}
+
+/// A GOT relocation of .text
+#[derive(Debug, Clone)]
+pub struct RelocInfo {
+    /// offset in .text of the word holding the GOT slot
+    pub offset: u32,
+    /// symbol table index of the symbol whose address goes into the slot
+    pub symbol: u32,
+    /// offset of the slot in the GOT, None if .text is encrypted
+    pub got_slot: Option<u32>,
+}
+
+/// The signature record
+#[derive(Debug, Clone)]
+pub struct SignatureInfo {
+    pub public_key: [u8; 32],
+    /// the signature matches the records before it under `public_key`
+    pub valid: bool,
+}
+
+/// Everything an image holds, decoded
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    pub prefix: Prefix,
+    pub layout: Layout,
+    pub records: Vec<RecordInfo>,
+    pub metadata: Vec<(String, String)>,
+    pub encryption: Option<Encryption>,
+    pub signature: Option<SignatureInfo>,
+    /// plain .text and .data, None if encrypted and no key was given
+    pub text: Option<Vec<u8>>,
+    pub data: Option<Vec<u8>>,
+    /// size in bytes of .text and .data as stored in the image
+    pub stored_text: usize,
+    pub stored_data: usize,
+    pub symbols: Vec<SymbolInfo>,
+    /// size in bytes of the symbol record, entries and names
+    pub l_symt: usize,
+    /// symbol table index of every function with a PLT entry
+    pub functions: Vec<u32>,
+    pub relocs: Vec<RelocInfo>,
+    pub data_relocs: Vec<DataReloc>,
+    /// exported functions entered through a trampoline in .text
+    pub trampolines: Vec<Trampoline>,
+    /// .text offsets of the constructors and destructors, in the order dl-lib runs them
+    pub constructors: Vec<u32>,
+    pub destructors: Vec<u32>,
+    /// size in bytes of the hash table over the exported symbols
+    pub l_hash: usize,
+}
+
+/// One line of the disassembly of .text
+#[derive(Debug, Clone)]
+pub enum DisasmLine {
+    /// a symbol starting at the next instruction
+    Label(String),
+    Insn {
+        offset: u32,
+        /// instruction bytes, as printed by llvm-objdump
+        bytes: String,
+        text: String,
+        /// what dl-lib patches or reads here
+        note: Option<String>,
+    },
+}
+
+/// name of the record with tag `tag`
+pub fn record_name(tag: u32) -> &'static str {
+    match tag {
+        image_format::RECORD_LAYOUT => "layout",
+        image_format::RECORD_TEXT => "text",
+        image_format::RECORD_DATA => "data",
+        image_format::RECORD_SYMBOLS => "symbols",
+        image_format::RECORD_RELOCS => "relocs",
+        image_format::RECORD_EXPORTS => "exports",
+        image_format::RECORD_DATA_RELOCS => "data relocs",
+        image_format::RECORD_SYMBOL_HASH => "symbol hash",
+        image_format::RECORD_ENCRYPTION => "encryption",
+        image_format::RECORD_METADATA => "metadata",
+        image_format::RECORD_DEBUG => "debug",
+        image_format::RECORD_SIGNATURE => "signature",
+        image_format::RECORD_TRAMPOLINES => "trampolines",
+        image_format::RECORD_INIT_ARRAY => "init array",
+        image_format::RECORD_FINI_ARRAY => "fini array",
+        tag if tag & image_format::RECORD_REQUIRED != 0 => "unknown (required)",
+        _ => "unknown",
+    }
+}
+
+fn kind_name(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::Local => "local",
+        SymbolKind::Exported => "exported",
+        SymbolKind::External => "external",
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// `record` decoded as a table of `T`, empty if the image doesn't have it
+fn table<T: Entry>(record: Option<&[u8]>, what: &str) -> Result<Vec<T>, Box<dyn Error>> {
+    let bad = || format!("malformed {} record", what);
+    Table::<T>::new(record.unwrap_or_default())
+        .ok_or_else(bad)?
+        .iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| bad().into())
+}
+
+impl SymbolInfo {
+    /// the name, or what stands in for it
+    pub fn display_name(&self) -> String {
+        match (&self.name, self.hash) {
+            (Some(name), _) => name.clone(),
+            (None, Some(hash)) => format!("#{:08x}", hash),
+            (None, None) => String::from("<local>"),
+        }
+    }
+
+    /// section the symbol lives in, "-" for external symbols
+    pub fn section(&self, layout: &Layout) -> &'static str {
+        match (self.entry.kind, self.entry.in_text) {
+            (SymbolKind::External, _) => "-",
+            (_, true) => "text",
+            // .bss follows .data
+            (_, false) if self.entry.address >= layout.data => "bss",
+            (_, false) => "data",
+        }
+    }
+}
+
+impl ImageInfo {
+    /// Decode `image`, which must at least have a valid prefix and layout record
+    pub fn parse(image: &[u8], options: &InspectOptions) -> Result<ImageInfo, Box<dyn Error>> {
+        let (prefix, body) =
+            Prefix::decode(image).map_err(|err| format!("not a module image: {:?}", err))?;
+        let mut records = Vec::new();
+        let mut payloads: BTreeMap<u32, &[u8]> = BTreeMap::new();
+        let mut signed = body;
+        for record in image_format::Records::new(body) {
+            let record = record.map_err(|err| format!("malformed records: {:?}", err))?;
+            records.push(RecordInfo {
+                tag: record.tag,
+                offset: IMAGE_PREFIX_LEN + record.offset,
+                len: record.payload.len(),
+            });
+            if record.tag == image_format::RECORD_SIGNATURE {
+                signed = &body[..record.offset];
+            }
+            payloads.entry(record.tag).or_insert(record.payload);
+        }
+        let record = |tag| payloads.get(&tag).copied();
+
+        let layout_record = record(image_format::RECORD_LAYOUT).ok_or("no layout record")?;
+        let layout = Layout::decode(layout_record).ok_or("malformed layout record")?;
+        let hashed_names = layout.flags & IMAGE_FLAG_HASHED_NAMES != 0;
+        let metadata = Metadata::new(record(image_format::RECORD_METADATA).unwrap_or_default())
+            .iter()
+            .map(|(key, value)| {
+                (
+                    String::from_utf8_lossy(key).into_owned(),
+                    String::from_utf8_lossy(value).into_owned(),
+                )
+            })
+            .collect();
+        let encryption = record(image_format::RECORD_ENCRYPTION)
+            .map(|record| Encryption::decode(record).ok_or("malformed encryption record"))
+            .transpose()?;
+        let signature = record(image_format::RECORD_SIGNATURE)
+            .map(|record| {
+                let signature = Signature::decode(record).ok_or("malformed signature record")?;
+                let valid = VerifyingKey::from_bytes(&signature.public_key)
+                    .and_then(|key| {
+                        key.verify_strict(
+                            signed,
+                            &Ed25519Signature::from_bytes(&signature.signature),
+                        )
+                    })
+                    .is_ok();
+                Ok::<_, Box<dyn Error>>(SignatureInfo {
+                    public_key: signature.public_key,
+                    valid,
+                })
+            })
+            .transpose()?;
+
+        // .text and .data as dl-lib runs them: decrypted, then decompressed
+        let stored_text = record(image_format::RECORD_TEXT).unwrap_or_default();
+        let stored_data = record(image_format::RECORD_DATA).unwrap_or_default();
+        let mut payload = Some([stored_text, stored_data].concat());
+        if let Some(encryption) = &encryption {
+            payload = match (&options.key, payload) {
+                (Some(key), Some(mut payload)) => {
+                    encryption::decrypt(key, encryption, layout_record, &mut payload)?;
+                    Some(payload)
+                }
+                _ => None,
+            };
+        }
+        let (text, data) = match payload {
+            Some(payload) => {
+                let (text, data) = payload.split_at(stored_text.len());
+                if layout.flags & IMAGE_FLAG_COMPRESSED != 0 {
+                    (
+                        Some(lz4::decompress(text, layout.text as usize)?),
+                        Some(lz4::decompress(data, layout.data as usize)?),
+                    )
+                } else {
+                    (Some(text.to_vec()), Some(data.to_vec()))
+                }
+            }
+            None => (None, None),
+        };
+
+        let symbol_record = record(image_format::RECORD_SYMBOLS).unwrap_or(&[0; 4]);
+        let symbol_table = Symbols::decode(symbol_record).ok_or("malformed symbols record")?;
+        let symbols = symbol_table
+            .entries
+            .iter()
+            .map(|entry| {
+                let entry = entry.ok_or("malformed symbol entry")?;
+                let hash = match entry.kind {
+                    SymbolKind::Local => None,
+                    _ => Some(
+                        symbol_table
+                            .name_hash(entry.name_offset, hashed_names)
+                            .ok_or("symbol name outside the names")?,
+                    ),
+                };
+                let name = match (hash, hashed_names) {
+                    (None, _) => None,
+                    (Some(_), false) => symbol_table
+                        .name(entry.name_offset)
+                        .map(|name| String::from_utf8_lossy(name).into_owned()),
+                    (Some(hash), true) => options
+                        .symbol_map
+                        .as_ref()
+                        .and_then(|map| map.name(hash))
+                        .map(String::from),
+                };
+                Ok(SymbolInfo { entry, hash, name })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let relocs = table::<GotReloc>(record(image_format::RECORD_RELOCS), "relocs")?
+            .into_iter()
+            .map(|reloc| RelocInfo {
+                offset: reloc.offset,
+                symbol: reloc.symbol,
+                got_slot: text.as_ref().and_then(|text| {
+                    let at = reloc.offset as usize;
+                    Some(u32::from_le_bytes(text.get(at..at + 4)?.try_into().ok()?))
+                }),
+            })
+            .collect();
+        let hash = record(image_format::RECORD_SYMBOL_HASH).unwrap_or_default();
+        SymbolHash::decode(hash).ok_or("malformed symbol hash record")?;
+
+        Ok(ImageInfo {
+            prefix,
+            layout,
+            records,
+            metadata,
+            encryption,
+            signature,
+            text,
+            data,
+            stored_text: stored_text.len(),
+            stored_data: stored_data.len(),
+            symbols,
+            l_symt: symbol_record.len() - 4,
+            functions: table(record(image_format::RECORD_EXPORTS), "exports")?,
+            relocs,
+            data_relocs: table(record(image_format::RECORD_DATA_RELOCS), "data relocs")?,
+            trampolines: table(record(image_format::RECORD_TRAMPOLINES), "trampolines")?,
+            constructors: table(record(image_format::RECORD_INIT_ARRAY), "init array")?,
+            destructors: table(record(image_format::RECORD_FINI_ARRAY), "fini array")?,
+            l_hash: hash.len(),
+        })
+    }
+
+    /// value of `key` in the metadata record
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// display name of symbol `index`, or a placeholder if there is no such symbol
+    fn symbol_name(&self, index: u32) -> String {
+        self.symbols
+            .get(index as usize)
+            .map_or_else(|| format!("<bad symbol {}>", index), |s| s.display_name())
+    }
+
+    fn flag_names(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.layout.flags & IMAGE_FLAG_HASHED_NAMES != 0 {
+            flags.push("hashed-names");
+        }
+        if self.layout.flags & IMAGE_FLAG_COMPRESSED != 0 {
+            flags.push("compressed");
+        }
+        if self.encryption.is_some() {
+            flags.push("encrypted");
+        }
+        if self.signature.is_some() {
+            flags.push("signed");
+        }
+        if !self.trampolines.is_empty() {
+            flags.push("trampolines");
+        }
+        flags
+    }
+
+    /// symbols other modules may use
+    pub fn exports(&self) -> impl Iterator<Item = &SymbolInfo> {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.entry.kind == SymbolKind::Exported)
+    }
+
+    /// symbols another module must define
+    pub fn imports(&self) -> impl Iterator<Item = &SymbolInfo> {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.entry.kind == SymbolKind::External)
+    }
+
+    /// what dl-lib does with the word at `offset` in .text, if anything
+    fn text_note(&self, offset: u32) -> Option<String> {
+        if let Some(trampoline) = self
+            .trampolines
+            .iter()
+            .find(|trampoline| trampoline.offset + TRAMPOLINE_STATIC_BASE as u32 == offset)
+        {
+            return Some(format!(
+                "R9 <- static base, for {}",
+                self.symbol_name(trampoline.symbol)
+            ));
+        }
+        let reloc = self.relocs.iter().find(|reloc| reloc.offset == offset)?;
+        Some(match reloc.got_slot {
+            Some(slot) => format!("GOT slot {:#x} <- {}", slot, self.symbol_name(reloc.symbol)),
+            None => format!("GOT slot <- {}", self.symbol_name(reloc.symbol)),
+        })
+    }
+
+    /// Disassemble .text with `objdump` (llvm-objdump), annotating the words dl-lib patches
+    pub fn disassemble(&self, objdump: &str) -> Result<Vec<DisasmLine>, Box<dyn Error>> {
+        let text = self
+            .text
+            .as_ref()
+            .ok_or(".text is encrypted, pass the key to disassemble it")?;
+        // mapping symbols tell code from the words the relocations point at
+        let mut symbols = vec![ElfSymbol::label("$t", 0)];
+        for reloc in &self.relocs {
+            symbols.push(ElfSymbol::label("$d", reloc.offset));
+            if !self
+                .relocs
+                .iter()
+                .any(|other| other.offset == reloc.offset + 4)
+            {
+                symbols.push(ElfSymbol::label("$t", reloc.offset + 4));
+            }
+        }
+        for (index, symbol) in self.symbols.iter().enumerate() {
+            if symbol.entry.kind == SymbolKind::External || !symbol.entry.in_text {
+                continue;
+            }
+            let name = match &symbol.name {
+                Some(name) => name.clone(),
+                None => format!("symbol_{}", index),
+            };
+            symbols.push(ElfSymbol {
+                name,
+                value: symbol.entry.address,
+                size: 0,
+                kind: ElfSymbolKind::Func,
+                global: symbol.entry.kind == SymbolKind::Exported,
+            });
+        }
+        for trampoline in &self.trampolines {
+            symbols.push(ElfSymbol {
+                name: toolchain::trampoline_name(&self.symbol_name(trampoline.symbol)),
+                value: trampoline.offset,
+                size: 0,
+                kind: ElfSymbolKind::Func,
+                global: false,
+            });
+        }
+        // constructors and destructors are usually local, without a symbol of their own
+        for (what, functions) in [
+            ("constructor", &self.constructors),
+            ("destructor", &self.destructors),
+        ] {
+            for (i, offset) in functions.iter().enumerate() {
+                if symbols.iter().any(|symbol| symbol.value == *offset) {
+                    continue;
+                }
+                symbols.push(ElfSymbol {
+                    name: format!("{}_{}", what, i),
+                    value: *offset,
+                    size: 0,
+                    kind: ElfSymbolKind::Func,
+                    global: false,
+                });
+            }
+        }
+        let object = elf::relocatable_object(
+            &ElfSection {
+                name: ".text",
+                data: text,
+                executable: true,
+                align: 4,
+            },
+            &symbols,
+        )?;
+        // removed when dropped, whether or not objdump succeeds
+        let mut file = tempfile::Builder::new()
+            .prefix("inspect-")
+            .suffix(".o")
+            .tempfile()?;
+        file.write_all(&object)?;
+        file.flush()?;
+        let output = Command::new(objdump)
+            .args(["-d", "--triple=thumbv7em-none-eabi"])
+            .arg(file.path())
+            .output();
+        file.close()?;
+        let output = output.map_err(|err| format!("can't run {}: {}", objdump, err))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                objdump,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| self.disasm_line(line))
+            .collect())
+    }
+
+    /// parse a line of llvm-objdump output, `<offset>: <bytes>\t<instruction>` or a label
+    fn disasm_line(&self, line: &str) -> Option<DisasmLine> {
+        if let Some(label) = line.strip_suffix(">:") {
+            let (_, name) = label.split_once('<')?;
+            // mapping symbols only steer the disassembler
+            return (!name.starts_with('$')).then(|| DisasmLine::Label(String::from(name)));
+        }
+        let (offset, rest) = line.trim_start().split_once(':')?;
+        let offset = u32::from_str_radix(offset, 16).ok()?;
+        let (bytes, text) = rest.trim_start().split_once('\t')?;
+        // pc-relative loads of a relocated word
+        let loads = text
+            .rsplit_once("@ 0x")
+            .and_then(|(_, target)| target.split_whitespace().next())
+            .and_then(|target| u32::from_str_radix(target, 16).ok())
+            .and_then(|target| self.text_note(target))
+            .map(|note| format!("loads {}", note));
+        Some(DisasmLine::Insn {
+            offset,
+            bytes: String::from(bytes.trim()),
+            text: text.replace('\t', " "),
+            note: self.text_note(offset).or(loads),
+        })
+    }
+
+    /// the whole image as JSON, with the disassembly if there is one
+    pub fn to_json(&self, disassembly: Option<&[DisasmLine]>) -> Value {
+        let symbol = |index: u32| {
+            json!({
+                "index": index,
+                "name": self.symbols.get(index as usize).and_then(|s| s.name.clone()),
+                "hash": self.symbols.get(index as usize).and_then(|s| s.hash),
+            })
+        };
+        json!({
+            "magic": self.prefix.magic,
+            "version": self.prefix.version,
+            "arch": self.prefix.arch,
+            "length": self.prefix.l_image,
+            "crc32": self.prefix.crc32,
+            "flags": self.flag_names(),
+            "metadata": self.metadata.iter().cloned().collect::<BTreeMap<_, _>>(),
+            "sizes": {
+                "text": self.layout.text,
+                "data": self.layout.data,
+                "bss": self.layout.bss,
+                "got": self.layout.got,
+                "stored_text": self.stored_text,
+                "stored_data": self.stored_data,
+                "symbol_table": self.l_symt,
+                "hash_table": self.l_hash,
+            },
+            "records": self.records.iter().map(|record| json!({
+                "tag": record.tag,
+                "name": record_name(record.tag),
+                "offset": record.offset,
+                "length": record.len,
+            })).collect::<Vec<_>>(),
+            "encryption": self.encryption.map(|encryption| json!({
+                "key_id": encryption.key_id,
+                "decrypted": self.text.is_some(),
+            })),
+            "signature": self.signature.as_ref().map(|signature| json!({
+                "public_key": hex(&signature.public_key),
+                "valid": signature.valid,
+            })),
+            "symbols": self.symbols.iter().enumerate().map(|(index, s)| json!({
+                "index": index,
+                "kind": kind_name(s.entry.kind),
+                "section": s.section(&self.layout),
+                "address": s.entry.address,
+                "hash": s.hash,
+                "name": s.name,
+            })).collect::<Vec<_>>(),
+            "imports": self.imports().map(|s| json!({"name": s.name, "hash": s.hash})).collect::<Vec<_>>(),
+            "exports": self.exports().map(|s| json!({"name": s.name, "hash": s.hash})).collect::<Vec<_>>(),
+            "functions": self.functions.iter().map(|index| symbol(*index)).collect::<Vec<_>>(),
+            "relocs": self.relocs.iter().map(|reloc| json!({
+                "offset": reloc.offset,
+                "got_slot": reloc.got_slot,
+                "symbol": symbol(reloc.symbol),
+            })).collect::<Vec<_>>(),
+            "data_relocs": self.data_relocs.iter().map(|reloc| json!({
+                "offset": reloc.offset,
+                "target": match reloc.target {
+                    RelocTarget::Text => json!("text"),
+                    RelocTarget::Data => json!("data"),
+                    RelocTarget::Symbol(index) => symbol(index),
+                },
+            })).collect::<Vec<_>>(),
+            "trampolines": self.trampolines.iter().map(|trampoline| json!({
+                "offset": trampoline.offset,
+                "function": symbol(trampoline.symbol),
+            })).collect::<Vec<_>>(),
+            "constructors": self.constructors,
+            "destructors": self.destructors,
+            "disassembly": disassembly.map(|lines| lines.iter().map(|line| match line {
+                DisasmLine::Label(name) => json!({"label": name}),
+                DisasmLine::Insn { offset, bytes, text, note } => json!({
+                    "offset": offset,
+                    "bytes": bytes,
+                    "text": text,
+                    "note": note,
+                }),
+            }).collect::<Vec<_>>()),
+        })
+    }
+}
+
+impl fmt::Display for ImageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = &self.layout;
+        writeln!(f, "module {}", self.metadata("name").unwrap_or("<unnamed>"))?;
+        writeln!(
+            f,
+            "  format version {}, arch {}, {} bytes, crc32 {:08x}",
+            self.prefix.version, self.prefix.arch, self.prefix.l_image, self.prefix.crc32
+        )?;
+        let flags = self.flag_names();
+        if !flags.is_empty() {
+            writeln!(f, "  flags: {}", flags.join(" "))?;
+        }
+        for (key, value) in &self.metadata {
+            writeln!(f, "  {} = {}", key, value)?;
+        }
+        if let Some(encryption) = &self.encryption {
+            let state = if self.text.is_some() {
+                "decrypted"
+            } else {
+                "not decrypted"
+            };
+            writeln!(f, "  encrypted with key {}, {}", encryption.key_id, state)?;
+        }
+        if let Some(signature) = &self.signature {
+            let state = if signature.valid { "valid" } else { "INVALID" };
+            writeln!(f, "  signed by {}, {}", hex(&signature.public_key), state)?;
+        }
+
+        writeln!(f, "\nsizes:")?;
+        writeln!(
+            f,
+            "  .text {:>6} (stored {})",
+            layout.text, self.stored_text
+        )?;
+        writeln!(
+            f,
+            "  .data {:>6} (stored {})",
+            layout.data, self.stored_data
+        )?;
+        writeln!(f, "  .bss  {:>6}", layout.bss)?;
+        writeln!(f, "  GOT   {:>6}", layout.got)?;
+        writeln!(
+            f,
+            "  symbol table {}, hash table {}",
+            self.l_symt, self.l_hash
+        )?;
+
+        writeln!(f, "\nrecords:")?;
+        writeln!(f, "  {:>8}  {:>6}  tag", "offset", "length")?;
+        for record in &self.records {
+            writeln!(
+                f,
+                "  {:#08x}  {:>6}  {:#010x} {}",
+                record.offset,
+                record.len,
+                record.tag,
+                record_name(record.tag)
+            )?;
+        }
+
+        writeln!(f, "\nsymbols:")?;
+        writeln!(
+            f,
+            "  {:>4}  {:<8}  {:<4}  {:>10}  {:>8}  name",
+            "idx", "kind", "sect", "address", "hash"
+        )?;
+        for (index, symbol) in self.symbols.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>4}  {:<8}  {:<4}  {:#010x}  {:>8}  {}",
+                index,
+                kind_name(symbol.entry.kind),
+                symbol.section(layout),
+                symbol.entry.address,
+                symbol
+                    .hash
+                    .map_or(String::new(), |hash| format!("{:08x}", hash)),
+                symbol.display_name()
+            )?;
+        }
+
+        writeln!(f, "\nimports:")?;
+        for symbol in self.imports() {
+            writeln!(f, "  {}", symbol.display_name())?;
+        }
+        writeln!(f, "\nexports:")?;
+        for (index, symbol) in self.symbols.iter().enumerate() {
+            if symbol.entry.kind != SymbolKind::Exported {
+                continue;
+            }
+            let plt = self.functions.contains(&(index as u32));
+            let trampoline = self
+                .trampolines
+                .iter()
+                .any(|trampoline| trampoline.symbol == index as u32);
+            let what = match (symbol.entry.in_text, plt, trampoline) {
+                (true, true, true) => "function, trampoline",
+                (true, true, false) => "function, PLT entry",
+                (true, false, _) => "code",
+                (false, _, _) => "data",
+            };
+            writeln!(f, "  {} ({})", symbol.display_name(), what)?;
+        }
+
+        writeln!(f, "\nrelocations (.text word -> GOT slot <- symbol):")?;
+        for reloc in &self.relocs {
+            let slot = reloc
+                .got_slot
+                .map_or(String::from("?"), |slot| format!("{:#x}", slot));
+            writeln!(
+                f,
+                "  {:#010x}  {:>6}  {}",
+                reloc.offset,
+                slot,
+                self.symbol_name(reloc.symbol)
+            )?;
+        }
+        writeln!(f, "\ndata relocations (.data word += base):")?;
+        for reloc in &self.data_relocs {
+            let target = match reloc.target {
+                RelocTarget::Text => String::from(".text"),
+                RelocTarget::Data => String::from(".data"),
+                RelocTarget::Symbol(index) => self.symbol_name(index),
+            };
+            writeln!(f, "  {:#010x}  {}", reloc.offset, target)?;
+        }
+        for (what, functions) in [
+            ("constructors (run after resolve)", &self.constructors),
+            ("destructors (run on unload)", &self.destructors),
+        ] {
+            if !functions.is_empty() {
+                writeln!(f, "\n{}:", what)?;
+                for offset in functions {
+                    writeln!(f, "  {:#010x}", offset)?;
+                }
+            }
+        }
+        if !self.trampolines.is_empty() {
+            writeln!(
+                f,
+                "\ntrampolines (.text offset, R9 switched before the call):"
+            )?;
+            for trampoline in &self.trampolines {
+                writeln!(
+                    f,
+                    "  {:#010x}  {}",
+                    trampoline.offset,
+                    self.symbol_name(trampoline.symbol)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisasmLine::Label(name) => write!(f, "\n<{}>:", name),
+            DisasmLine::Insn {
+                offset,
+                bytes,
+                text,
+                note,
+            } => {
+                write!(f, "  {:>6x}:  {:<12}  {}", offset, bytes, text)?;
+                if let Some(note) = note {
+                    write!(f, "  ; {}", note)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
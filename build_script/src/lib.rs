@@ -0,0 +1,20 @@
+//! Turn relocatable thumbv7em objects into module images loadable by dl-lib
+//!
+//! The `build_script` binary is a thin command-line wrapper around [`ImageBuilder`],
+//! firmware crates can use the same API from their `build.rs`. The `inspect` binary
+//! prints what an image holds, see [`inspect::ImageInfo`]. The `bundle` binary packs
+//! several images into one bundle, see [`BundleBuilder`].
+pub mod builder;
+pub mod bundle;
+pub mod image;
+pub mod inspect;
+pub mod output;
+pub mod toolchain;
+pub mod utils;
+
+pub use builder::ImageBuilder;
+pub use bundle::BundleBuilder;
+pub use image::{ImageOptions, ImageSizes, ModuleImage};
+pub use output::OutputFormat;
+pub use toolchain::Toolchain;
+pub use utils::symbol_map::SymbolMap;
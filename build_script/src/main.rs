@@ -1,306 +1,166 @@
-mod utils;
-use utils::{literals, readelf, relocations};
-use utils::{relocations::RelocationType, symbols::SymbolType};
-
-use md5;
-use object;
-use object::{Object, ObjectSection, ObjectSymbol, SectionIndex};
-use std::collections::{HashMap, HashSet};
-use std::{error::Error, fs, io::Write, process::Command};
-
-// use crate::{TEST, TEST2, TEST3};
-
-fn trampoline_entry_name(func: &str) -> String {
-    let name_prefix = format!("{:x}", md5::compute(func.as_bytes()));
-    let name_prefix_8 = name_prefix.chars().take(8).collect::<String>();
-    format!("__{}__{}", name_prefix_8, func)
+use build_script::inspect::{ImageInfo, InspectOptions};
+use build_script::utils::encryption::EncryptionKey;
+use build_script::utils::{relocations, signing};
+use build_script::{output, toolchain, ImageBuilder, OutputFormat};
+
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::{error::Error, fs, process};
+
+/// Convert relocatable objects into an image loadable by dl-lib
+#[derive(Parser, Debug)]
+struct Args {
+    /// objects to link into the module
+    #[clap(required = true)]
+    objects: Vec<String>,
+    /// where to write the image, defaults to <NAME> with the extension of the format
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// how to write the image: bin, rust (static array), c (header), ihex, srec or elf (object)
+    #[clap(long, default_value = "bin")]
+    format: String,
+    /// flash address the ihex and srec formats put the image at
+    #[clap(long, value_name = "ADDRESS", parse(try_from_str = output::parse_address))]
+    base: Option<u32>,
+    /// section holding the image in the elf format, defaults to .rodata.<NAME>
+    #[clap(long)]
+    section: Option<String>,
+    /// module name, defaults to the file stem of the first object
+    #[clap(short, long)]
+    name: Option<String>,
+    /// module version, stored in the image and listed by bundles holding it
+    #[clap(long, value_name = "VERSION")]
+    module_version: Option<String>,
+    /// linker script used to lay out the module
+    #[clap(short = 'T', long, default_value = toolchain::DEFAULT_LINKER_SCRIPT)]
+    linker_script: String,
+    /// clang binary, used to assemble the trampolines
+    #[clap(long, default_value = "clang")]
+    clang: String,
+    /// ld.lld binary
+    #[clap(long, default_value = "ld.lld")]
+    ld_lld: String,
+    /// relocation type to accept even though dl-lib can't apply it, by name (R_ARM_V4BX) or number
+    #[clap(long = "allow-reloc", value_name = "TYPE", parse(try_from_str = parse_reloc))]
+    allow_relocs: Vec<u32>,
+    /// store 32-bit hashes instead of symbol names, the names go to the map file
+    #[clap(long)]
+    hashed_names: bool,
+    /// compress .text and .data, the loader runs such a module from RAM
+    #[clap(long)]
+    compress: bool,
+    /// enter exported functions through trampolines that switch R9, instead of the
+    /// loader's PLT, the loader runs such a module from RAM. Exported functions must not
+    /// take arguments on the stack (more than four argument words, or variadic), this
+    /// isn't detected
+    #[clap(long)]
+    trampolines: bool,
+    /// encrypt .text and .data with this AES-256 key, a file with the raw 32 bytes
+    #[clap(long, value_name = "KEY")]
+    encrypt: Option<PathBuf>,
+    /// id the loader's key provider knows the --encrypt key by
+    #[clap(long, default_value_t = 0)]
+    key_id: u32,
+    /// sign the image with this Ed25519 key, a file with the raw 32-byte secret
+    #[clap(long, value_name = "KEY")]
+    sign: Option<PathBuf>,
+    /// write the public key of --sign here, 32 raw bytes for dl-lib's trusted keys
+    #[clap(long, value_name = "FILE", requires = "sign")]
+    public_key: Option<PathBuf>,
+    /// where to write the symbol map, defaults to <OUTPUT>.map with --hashed-names
+    #[clap(long)]
+    map: Option<PathBuf>,
+    /// symbol map of a dependency, checked for symbols with the same hash
+    #[clap(long = "dep-map", value_name = "MAP")]
+    dep_maps: Vec<PathBuf>,
+    /// print the toolchain output and what the resulting image holds
+    #[clap(short, long)]
+    verbose: bool,
 }
 
-/// For a given object file, for each contained public function,
-/// generate a trampoline such that R9 will be updated before calling
-/// into the actual function.
-///
-/// The trampolines have the following layout:
-/// __hash1_func1:
-///     push    {r9, lr}
-///     movw    r11, #0  // r11 will hold func1's runtime address
-///     movt    r11, #0
-///     b       common_trampoline
-/// __hash2_func2:
-///     push    {r9, lr}
-///     movw    r11, #0
-///     movt    r11, #0
-///     b       common_trampoline
-/// __hash3__func3:
-///     ...
-/// common_trampoline:
-///     movw    r9, #0    // switch R9
-///     movt    r9, #0
-///     blx     r11       // call into function
-///     pop     {r9, pc}
-fn compile_trampoline(obj_path: &str, module_name: &str) {
-    let pub_funcs = readelf::get_pub_funcs(obj_path).unwrap();
-
-    let func_trampolines = pub_funcs.iter().fold(String::new(), |mut folded, func| {
-        folded.push_str(&format!(
-            crate::FUNPRE!(),
-            s = trampoline_entry_name(func),
-            modulename = trampoline_entry_name(module_name)
-        ));
-        folded
-    });
-
-    let common_trampoline = format!(crate::OBJPRE!(), s = trampoline_entry_name(module_name));
-
-    let asm = format!(
-        "{}{}{}{}",
-        literals::ASM_HEAD,
-        func_trampolines,
-        common_trampoline,
-        literals::ASM_TAIL
-    );
-
-    fs::write("asm.s", asm).unwrap();
-
-    // TODO: change _pre
-    let trampo_path = obj_path.replace(".o", "_pre.o");
-
-    let assemble_cmd = format!(crate::ASM_CMD!(), asm = "asm.s", elf = trampo_path);
-
-    // Invoke compiler to compile the generated asm file into an object file.
-    let output = Command::new("bash")
-        .arg("-c")
-        .arg(assemble_cmd)
-        .output()
-        .unwrap();
-    println!("ASM: {:?}", output);
-    if !output.status.success() {
-        panic!("Assembler failed!");
-    }
+fn parse_reloc(name: &str) -> Result<u32, String> {
+    relocations::parse_relocation_type(name)
+        .ok_or_else(|| format!("unknown relocation type `{}`", name))
 }
 
-// link given objects into out.elf
-fn link_objects(objs: &Vec<String>, output: &str) {
-    let input = objs.join(" ");
-    let link_cmd = format!(crate::LINK_CMD!(), input = input, output = output);
-
-    let output = Command::new("bash")
-        .arg("-c")
-        .arg(link_cmd)
-        .output()
-        .unwrap();
-    println!("LNK: {:?}", output);
-    if !output.status.success() {
-        panic!("Linker failed!");
+// Statically link the raw_objects[] into single dynamic library.
+fn main() {
+    let args = Args::parse();
+    if let Err(err) = build(args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
     }
 }
 
-/// For a given object file, and its public functions,
-/// generate a binary image that can be parsed by dl-lib
-/// The image has the following layout, numbers have width=4 and are in little-endian order
-///
-/// num_global_functions, num_relocs, raw_symbol_table_length
-/// code section length, data section length, bss section length, num_symbols
-/// code section
-/// data section
-/// Symbol Table:
-///     symbol1 index in flat symbol names, symbol1 address
-///     symbol2 index in flat symbol names, symbol2 address
-/// ...
-/// flat symbol names = symbol1.name 0 symbol2.name 0 ...
-/// Relocation table (functions)
-///     reloc1 offset, reloc1 index in symbol table
-///     reloc2 offset, reloc2 index in symbol table
-/// ...
-/// func1's index in symbol table
-/// func2's index in symbol table
-/// ...
-///
-fn make_image(obj: &String, glb_funcs: Vec<String>) -> Result<Vec<u8>, Box<dyn Error>> {
-    let bin_data = fs::read(obj)?;
-    let obj_file = object::File::parse(&*bin_data)?;
-    let code_section = obj_file.section_by_name(".text").unwrap().data()?;
-    let data_section = obj_file.section_by_name(".data").unwrap().data()?;
-    let bss_section = obj_file.section_by_name(".bss").unwrap().data()?;
-    let filtered_symbols: Vec<_> = obj_file
-        .symbols()
-        .filter(|s| {
-            let name = s.name().unwrap();
-            !name.is_empty()
-                && !name.starts_with("$t")
-                && !name.starts_with("$d")
-                // TODO: change fixed module
-                && !name.ends_with("module")
-        })
-        .collect();
-
-    let mut type_by_name: HashMap<String, SymbolType> = HashMap::new();
-    let mut section_by_name: HashMap<String, SectionIndex> = HashMap::new();
-    let mut address_by_name: HashMap<String, u64> = HashMap::new();
-
-    // get symbol type (Exported, External, Local, None), section index, and address
-    for symbol in filtered_symbols {
-        let name = String::from(symbol.name().unwrap());
-        let symbol_type = match (symbol.is_global(), symbol.is_undefined(), symbol.kind()) {
-            (true, false, _) => Some(SymbolType::Exported),
-            (true, _, _) => Some(SymbolType::External),
-            (_, _, object::SymbolKind::File) => None,
-            (_, _, _) => Some(SymbolType::Local),
-        };
-        if let Some(symbol_type) = symbol_type {
-            type_by_name.insert(name.clone(), symbol_type);
-            if let Some(index) = symbol.section_index() {
-                section_by_name.insert(name.clone(), index);
-            }
-            address_by_name.insert(name.clone(), symbol.address());
-        }
+fn build(args: Args) -> Result<(), Box<dyn Error>> {
+    let module_name = match args.name {
+        Some(name) => name,
+        None => Path::new(&args.objects[0])
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let format = OutputFormat::from_name(&args.format, args.base, args.section)?;
+    let output = args
+        .output
+        .unwrap_or_else(|| PathBuf::from(format!("{}.{}", module_name, format.extension())));
+    // intermediate files go next to the image
+    let out_dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut builder = ImageBuilder::new(module_name)
+        .objects(&args.objects)
+        .out_dir(out_dir)
+        .linker_script(args.linker_script)
+        .clang(args.clang)
+        .ld_lld(args.ld_lld)
+        .verbose(args.verbose)
+        .hashed_names(args.hashed_names)
+        .compress(args.compress)
+        .trampolines(args.trampolines);
+    if let Some(version) = args.module_version {
+        builder = builder.version(version);
     }
-
-    // switch to low-level read api, something not right in the unified read.
-    let vec_relocations = relocations::get_known_relocations(obj).unwrap();
-    let reloc_names: HashSet<_> = vec_relocations.iter().map(|var| var.name.clone()).collect();
-
-    let mut image: Vec<u8> = Vec::new();
-
-    let mut sym_names: Vec<String> = Vec::new();
-    // Symbol Table: process names
-    for (k, v) in &type_by_name {
-        // exclude unused local symbol
-        // only used local symbols and external/exported symbols needs further processing
-        if reloc_names.contains(k) {
-            sym_names.push(k.clone());
-        } else if let SymbolType::External | SymbolType::Exported = v {
-            sym_names.push(k.clone());
-        }
+    for r_type in args.allow_relocs {
+        builder = builder.allow_relocation(r_type);
     }
-
-    let flat_sym_names: Vec<_> = sym_names
-        .iter()
-        .flat_map(|name| {
-            if let Some(SymbolType::External) | Some(SymbolType::Exported) = type_by_name.get(name)
-            {
-                format!("{}\0", name).as_bytes().to_vec()
-            } else {
-                "".as_bytes().to_vec()
-            }
-        })
-        .collect();
-    let sym_table_len = sym_names.len() * 8 + flat_sym_names.len();
-
-    let sym_table_idx: HashMap<String, u32> = sym_names
-        .iter()
-        .enumerate()
-        .map(|(idx, name)| (name.clone(), idx as u32))
-        .collect();
-
-    image.extend(&glb_funcs.len().to_le_bytes()[0..4]);
-    image.extend(&vec_relocations.len().to_le_bytes()[0..4]);
-    image.extend(&sym_table_len.to_le_bytes()[0..4]);
-    image.extend(&code_section.len().to_le_bytes()[0..4]);
-    image.extend(&data_section.len().to_le_bytes()[0..4]);
-    image.extend(&bss_section.len().to_le_bytes()[0..4]);
-    image.extend(&sym_names.len().to_le_bytes()[0..4]);
-
-    image.extend(code_section);
-    image.extend(data_section);
-
-    let mut flat_sym_names_len = 0;
-    // Write Symbol table
-    image.extend(
-        sym_names
-            .iter()
-            .flat_map(|name| {
-                let addr_offset = if let Some(SectionIndex(1)) = section_by_name.get(name) {
-                    0
-                } else {
-                    code_section.len()
-                };
-                let addr = if let SymbolType::External = type_by_name[name] {
-                    0
-                } else {
-                    address_by_name[name] as usize - addr_offset
-                };
-                // if its a variable, address equals code_section.len() + its index in datas * 4
-                // if its a function, address equals its entry, 0 for external symbols
-                let type_data = match type_by_name[name] {
-                    SymbolType::Local => 0,
-                    SymbolType::Exported => 1,
-                    SymbolType::External => 2,
-                } + if let Some(SectionIndex(1)) = section_by_name.get(name) {
-                    4
-                } else {
-                    0
-                } << 28;
-                let x = type_data | (flat_sym_names_len as u32);
-                if let SymbolType::Exported | SymbolType::External = type_by_name[name] {
-                    flat_sym_names_len += name.len() + 1;
-                }
-                let mut sym_entry: Vec<u8> = Vec::new();
-                sym_entry.extend(&x.to_le_bytes()[0..4]);
-                sym_entry.extend(&addr.to_le_bytes()[0..4]);
-                sym_entry
-            })
-            .collect::<Vec<_>>(),
-    );
-
-    image.extend(flat_sym_names);
-    // Write Relocation table
-    image.extend(
-        vec_relocations
-            .iter()
-            .flat_map(|reloc| {
-                let mut reloc_entry: Vec<u8> = Vec::new();
-                // address to .word
-                reloc_entry.extend(&reloc.r_offset.to_le_bytes()[0..4]);
-                reloc_entry.extend(&sym_table_idx[&reloc.name].to_le_bytes()[0..4]);
-                reloc_entry
-            })
-            .collect::<Vec<_>>(),
-    );
-
-    // Write every global function's index
-    image.extend(
-        glb_funcs
-            .iter()
-            .flat_map(|name| sym_table_idx.get(name).unwrap().to_le_bytes())
-            .collect::<Vec<_>>(),
-    );
-    // strip .bss
-    // image.extend(bss_section);
-    // Write image to specific file
-
-    Ok(image)
-}
-
-// Statically link the raw_objects[] into single dynamic library.
-fn main() {
-    let module_name = "module_def";
-    let input_obj_paths: Vec<String> = vec![String::from(format!("{}.o", module_name))];
-
-    // Compile trampoline for each input object file.
-    for path in &input_obj_paths {
-        // TODO: change fixed "module"
-        compile_trampoline(path, module_name);
+    for map in args.dep_maps {
+        builder = builder.dependency_map(map);
     }
-
-    let glb_funcs: Vec<_> = input_obj_paths
-        .iter()
-        .flat_map(|path| readelf::get_pub_funcs(path).unwrap())
-        .collect();
-
-    // let trampoline_paths: Vec<_> = input_obj_paths
-    //     .iter()
-    //     .map(|path| path.replace(".o", "_pre.o"))
-    //     .collect();
-
-    let linker_input_paths = input_obj_paths;
-    // linker_input_paths.extend(input_obj_paths);
-    link_objects(&linker_input_paths, &format!("{}.elf", module_name));
-
-    let image = make_image(&format!("{}.elf", module_name), glb_funcs).unwrap();
+    let encryption_key = args
+        .encrypt
+        .map(|key| EncryptionKey::read(args.key_id, key))
+        .transpose()?;
+    if let Some(key) = &encryption_key {
+        builder = builder.encrypt(key.clone());
+    }
+    if let Some(key) = &args.sign {
+        let key = signing::read_signing_key(key)?;
+        if let Some(path) = &args.public_key {
+            fs::write(path, signing::public_key(&key))?;
+        }
+        builder = builder.sign(key);
+    }
+    let image = builder.build()?;
     // handling results
-    let mut file =
-        fs::File::create(format!("../dl-lib/{}.bin", module_name)).expect("Open binary.rs failed");
-    file.write_all(&image).expect("Write failed");
-    println!("{:?}", image);
+    image.write_as(&output, &format)?;
+    let map = match args.map {
+        Some(map) => Some(map),
+        None if args.hashed_names => Some(output.with_extension("map")),
+        None => None,
+    };
+    if let Some(map) = map {
+        image.symbol_map().write(map)?;
+    }
+    if args.verbose {
+        let options = InspectOptions {
+            symbol_map: Some(image.symbol_map()),
+            key: encryption_key,
+        };
+        print!("{}", ImageInfo::parse(&image.bytes, &options)?);
+    }
+    Ok(())
 }
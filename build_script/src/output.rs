@@ -0,0 +1,298 @@
+//! Files an image or a bundle can be written as, for the different ways firmware embeds them
+use crate::utils::elf::{self, ElfSection, ElfSymbol, ElfSymbolKind};
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// alignment of the embedded bytes, dl-lib runs .text in place and reads the records as words
+const ALIGN: u32 = 4;
+/// data bytes per line of the source outputs and per HEX/S-record record
+const BYTES_PER_LINE: usize = 16;
+/// longest module name put into the S0 header record
+const S0_HEADER_LEN: usize = 64;
+
+/// How `render` writes out an image or bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// the bytes as they are
+    Bin,
+    /// Rust source defining a `static` byte slice backed by a 4-byte aligned array
+    Rust,
+    /// C header defining a 4-byte aligned array and its length
+    CHeader,
+    /// Intel HEX with the bytes at flash address `base`
+    IntelHex { base: u32 },
+    /// Motorola S-record (S3) with the bytes at flash address `base`
+    SRecord { base: u32 },
+    /// relocatable ELF object holding the bytes in `section`, `.rodata.<name>` if None,
+    /// between the symbols `_binary_<name>_start` and `_binary_<name>_end`
+    Elf { section: Option<String> },
+}
+
+impl OutputFormat {
+    /// Format by its name on the command line: bin, rust, c, ihex, srec or elf.
+    /// ihex and srec need `base`, `section` only applies to elf.
+    pub fn from_name(
+        name: &str,
+        base: Option<u32>,
+        section: Option<String>,
+    ) -> Result<OutputFormat, String> {
+        let base = || base.ok_or_else(|| format!("output format {} needs a flash base", name));
+        let format = match name {
+            "bin" => OutputFormat::Bin,
+            "rust" => OutputFormat::Rust,
+            "c" => OutputFormat::CHeader,
+            "ihex" => OutputFormat::IntelHex { base: base()? },
+            "srec" => OutputFormat::SRecord { base: base()? },
+            "elf" => return Ok(OutputFormat::Elf { section }),
+            _ => return Err(format!("unknown output format `{}`", name)),
+        };
+        match section {
+            Some(_) => Err(String::from("only the elf output format has a section")),
+            None => Ok(format),
+        }
+    }
+
+    /// usual file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Bin => "bin",
+            OutputFormat::Rust => "rs",
+            OutputFormat::CHeader => "h",
+            OutputFormat::IntelHex { .. } => "hex",
+            OutputFormat::SRecord { .. } => "srec",
+            OutputFormat::Elf { .. } => "o",
+        }
+    }
+}
+
+/// A flash address as given on the command line, hex with `0x` or decimal
+pub fn parse_address(address: &str) -> Result<u32, String> {
+    let parsed = match address.strip_prefix("0x").or(address.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => address.parse(),
+    };
+    parsed.map_err(|_| format!("`{}` is not a 32-bit address", address))
+}
+
+/// `name` turned into an identifier: anything but ASCII letters, digits and `_` becomes `_`
+fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        id.insert(0, '_');
+    }
+    id
+}
+
+/// `bytes` as comma-separated hex literals, `BYTES_PER_LINE` to a line
+fn byte_lines(bytes: &[u8], indent: &str) -> String {
+    let mut out = String::new();
+    for line in bytes.chunks(BYTES_PER_LINE) {
+        out.push_str(indent);
+        let line: Vec<_> = line.iter().map(|byte| format!("0x{:02x},", byte)).collect();
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+fn rust_source(name: &str, bytes: &[u8]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// {}, {} bytes, generated by build_script",
+        name,
+        bytes.len()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "pub static {}: &[u8] = {{",
+        identifier(name).to_uppercase()
+    )
+    .unwrap();
+    writeln!(out, "    #[repr(C, align({}))]", ALIGN).unwrap();
+    writeln!(out, "    struct Aligned<const N: usize>([u8; N]);").unwrap();
+    writeln!(
+        out,
+        "    static ALIGNED: Aligned<{}> = Aligned([",
+        bytes.len()
+    )
+    .unwrap();
+    out.push_str(&byte_lines(bytes, "        "));
+    writeln!(out, "    ]);").unwrap();
+    writeln!(out, "    &ALIGNED.0").unwrap();
+    writeln!(out, "}};").unwrap();
+    out
+}
+
+fn c_header(name: &str, bytes: &[u8]) -> String {
+    let id = identifier(name);
+    let upper = id.to_uppercase();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/* {}, {} bytes, generated by build_script */",
+        name,
+        bytes.len()
+    )
+    .unwrap();
+    writeln!(out, "#ifndef {}_H\n#define {}_H\n", upper, upper).unwrap();
+    writeln!(out, "#define {}_LEN {}u\n", upper, bytes.len()).unwrap();
+    writeln!(
+        out,
+        "static const unsigned char {}[{}_LEN] __attribute__((aligned({}))) = {{",
+        id, upper, ALIGN
+    )
+    .unwrap();
+    out.push_str(&byte_lines(bytes, "    "));
+    writeln!(out, "}};\n\n#endif").unwrap();
+    out
+}
+
+/// one Intel HEX record: length, address, type, data, two's complement checksum
+fn hex_record(out: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let mut record = vec![data.len() as u8];
+    record.extend(address.to_be_bytes());
+    record.push(kind);
+    record.extend(data);
+    let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(sum.wrapping_neg());
+    out.push(':');
+    for byte in record {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+fn intel_hex(base: u32, bytes: &[u8]) -> Result<String, Box<dyn Error>> {
+    check_fits(base, bytes)?;
+    let mut out = String::new();
+    let mut segment = None;
+    for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let address = base + (i * BYTES_PER_LINE) as u32;
+        // data records hold 16-bit addresses, a line may not cross into the next 64 KB
+        for (at, part) in split_at_segment(address, line) {
+            let upper = (at >> 16) as u16;
+            if segment != Some(upper) {
+                hex_record(&mut out, 0, 4, &upper.to_be_bytes());
+                segment = Some(upper);
+            }
+            hex_record(&mut out, at as u16, 0, part);
+        }
+    }
+    hex_record(&mut out, 0, 1, &[]);
+    Ok(out)
+}
+
+/// `line` starting at `address`, split where it crosses a 64 KB boundary
+fn split_at_segment(address: u32, line: &[u8]) -> Vec<(u32, &[u8])> {
+    let to_boundary = (0x1_0000 - (address & 0xffff)) as usize;
+    if line.len() <= to_boundary {
+        return vec![(address, line)];
+    }
+    let (first, second) = line.split_at(to_boundary);
+    vec![(address, first), (address + to_boundary as u32, second)]
+}
+
+/// one S-record: type, count, address, data, ones' complement checksum
+fn s_record(out: &mut String, kind: char, address: &[u8], data: &[u8]) {
+    let mut record = vec![(address.len() + data.len() + 1) as u8];
+    record.extend(address);
+    record.extend(data);
+    let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    record.push(!sum);
+    out.push('S');
+    out.push(kind);
+    for byte in record {
+        write!(out, "{:02X}", byte).unwrap();
+    }
+    out.push('\n');
+}
+
+fn s_records(name: &str, base: u32, bytes: &[u8]) -> Result<String, Box<dyn Error>> {
+    check_fits(base, bytes)?;
+    let mut out = String::new();
+    // the header is free-form, cut to what a record holds
+    let header = &name.as_bytes()[..name.len().min(S0_HEADER_LEN)];
+    s_record(&mut out, '0', &[0, 0], header);
+    let lines = bytes.chunks(BYTES_PER_LINE);
+    let n_lines = lines.len();
+    for (i, line) in lines.enumerate() {
+        let address = base + (i * BYTES_PER_LINE) as u32;
+        s_record(&mut out, '3', &address.to_be_bytes(), line);
+    }
+    // record count, S5 for 16 bits and S6 for 24
+    match u16::try_from(n_lines) {
+        Ok(count) => s_record(&mut out, '5', &count.to_be_bytes(), &[]),
+        Err(_) => s_record(&mut out, '6', &(n_lines as u32).to_be_bytes()[1..], &[]),
+    }
+    s_record(&mut out, '7', &base.to_be_bytes(), &[]);
+    Ok(out)
+}
+
+/// `bytes` placed at `base` must end within the 32-bit address space
+fn check_fits(base: u32, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    match u32::try_from(bytes.len())
+        .ok()
+        .and_then(|len| base.checked_add(len))
+    {
+        Some(_) => Ok(()),
+        None => Err(format!("{} bytes don't fit at {:#010x}", bytes.len(), base).into()),
+    }
+}
+
+fn elf_object(
+    name: &str,
+    section: &Option<String>,
+    bytes: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let id = identifier(name);
+    let section = section.clone().unwrap_or_else(|| format!(".rodata.{}", id));
+    let symbol = |suffix: &str, value: usize, size: usize| ElfSymbol {
+        name: format!("_binary_{}_{}", id, suffix),
+        value: value as u32,
+        size: size as u32,
+        kind: ElfSymbolKind::Object,
+        global: true,
+    };
+    elf::relocatable_object(
+        &ElfSection {
+            name: &section,
+            data: bytes,
+            executable: false,
+            align: ALIGN,
+        },
+        &[
+            symbol("start", 0, bytes.len()),
+            symbol("end", bytes.len(), 0),
+        ],
+    )
+}
+
+/// `bytes` written out in `format`, `name` names the array, symbols or S-record header
+pub fn render(format: &OutputFormat, name: &str, bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(match format {
+        OutputFormat::Bin => bytes.to_vec(),
+        OutputFormat::Rust => rust_source(name, bytes).into_bytes(),
+        OutputFormat::CHeader => c_header(name, bytes).into_bytes(),
+        OutputFormat::IntelHex { base } => intel_hex(*base, bytes)?.into_bytes(),
+        OutputFormat::SRecord { base } => s_records(name, *base, bytes)?.into_bytes(),
+        OutputFormat::Elf { section } => elf_object(name, section, bytes)?,
+    })
+}
+
+/// write `bytes` to `path` in `format`, see `render`
+pub fn write(
+    path: impl AsRef<Path>,
+    format: &OutputFormat,
+    name: &str,
+    bytes: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    fs::write(path, render(format, name, bytes)?)?;
+    Ok(())
+}
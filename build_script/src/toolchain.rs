@@ -0,0 +1,153 @@
+//! Invocation of clang and ld.lld on the module objects
+use crate::utils::literals;
+use std::path::Path;
+use std::{error::Error, fs, process::Command};
+
+/// linker script shipped with build_script, lays out .text before .data
+pub const DEFAULT_LINKER_SCRIPT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/code_before_data.ld");
+
+/// External tools invoked while building an image
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    pub clang: String,
+    pub ld_lld: String,
+    pub linker_script: String,
+    pub verbose: bool,
+}
+
+impl Default for Toolchain {
+    fn default() -> Self {
+        Toolchain {
+            clang: String::from("clang"),
+            ld_lld: String::from("ld.lld"),
+            linker_script: String::from(DEFAULT_LINKER_SCRIPT),
+            verbose: false,
+        }
+    }
+}
+
+impl Toolchain {
+    /// run a shell command, echoing its output in verbose mode or if it fails
+    pub fn run(&self, tag: &str, cmd: String) -> Result<(), Box<dyn Error>> {
+        let output = Command::new("bash").arg("-c").arg(&cmd).output()?;
+        if self.verbose {
+            println!("{}: {:?}", tag, output);
+        }
+        if !output.status.success() {
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            return Err(format!("{} failed: {}", tag, cmd).into());
+        }
+        Ok(())
+    }
+}
+
+/// name of the trampoline of exported function `func`
+pub fn trampoline_name(func: &str) -> String {
+    format!("{}{}", literals::TRAMPOLINE_PREFIX, func)
+}
+
+/// For the exported functions of a module, generate trampolines that switch R9 to the
+/// module's static base before calling into the function, and return the object to link.
+///
+/// Each trampoline is hidden, so it doesn't become an export itself, and has the layout
+/// of `image_format::Trampoline`, the loader patches the movw/movt pair:
+/// __dl_trampoline_func1:
+///     push.w  {r9, lr}
+///     movw    r9, #0
+///     movt    r9, #0
+///     bl      func1
+///     pop.w   {r9, pc}
+/// __dl_trampoline_func2:
+///     ...
+pub fn compile_trampolines(
+    toolchain: &Toolchain,
+    funcs: &[String],
+    module_name: &str,
+    out_dir: &Path,
+) -> Result<String, Box<dyn Error>> {
+    let trampolines = funcs.iter().fold(String::new(), |mut folded, func| {
+        folded.push_str(&format!(
+            crate::TRAMPOLINE!(),
+            s = trampoline_name(func),
+            func = func
+        ));
+        folded
+    });
+    let asm = format!(
+        "{}{}{}",
+        literals::ASM_HEAD,
+        trampolines,
+        literals::ASM_TAIL
+    );
+
+    let asm_path = out_dir.join(format!("{}_trampolines.s", module_name));
+    let trampoline_path = out_dir.join(format!("{}_trampolines.o", module_name));
+    fs::write(&asm_path, asm)?;
+
+    let assemble_cmd = format!(
+        crate::ASM_CMD!(),
+        clang = toolchain.clang,
+        asm = asm_path.display(),
+        elf = trampoline_path.display()
+    );
+    toolchain.run("ASM", assemble_cmd)?;
+    Ok(trampoline_path.to_string_lossy().into_owned())
+}
+
+/// For the functions of other modules called with a plain `bl`/`b.w`,
+/// generate veneers that reach them through the GOT, and return the object to link.
+///
+/// Each veneer takes the name of the function it stands for, so the linker points
+/// the calls at it, and loads its target from the GOT slot of `__dl_import_<func>`:
+/// func1:
+///     ldr     r12, 1f
+///     ldr     r12, [r9, r12]
+///     bx      r12
+/// 1:  .word   __dl_import_func1(GOT)
+pub fn compile_veneers(
+    toolchain: &Toolchain,
+    imports: &[String],
+    module_name: &str,
+    out_dir: &Path,
+) -> Result<String, Box<dyn Error>> {
+    let veneers = imports.iter().fold(String::new(), |mut folded, func| {
+        folded.push_str(&format!(
+            crate::VENEER!(),
+            s = func,
+            import = format!("{}{}", literals::IMPORT_PREFIX, func)
+        ));
+        folded
+    });
+    let asm = format!("{}{}{}", literals::ASM_HEAD, veneers, literals::ASM_TAIL);
+
+    let asm_path = out_dir.join(format!("{}_veneers.s", module_name));
+    let veneer_path = out_dir.join(format!("{}_veneers.o", module_name));
+    fs::write(&asm_path, asm)?;
+
+    let assemble_cmd = format!(
+        crate::ASM_CMD!(),
+        clang = toolchain.clang,
+        asm = asm_path.display(),
+        elf = veneer_path.display()
+    );
+    toolchain.run("ASM", assemble_cmd)?;
+    Ok(veneer_path.to_string_lossy().into_owned())
+}
+
+// link given objects into output
+pub fn link_objects(
+    toolchain: &Toolchain,
+    objs: &[String],
+    output: &str,
+) -> Result<(), Box<dyn Error>> {
+    let input = objs.join(" ");
+    let link_cmd = format!(
+        crate::LINK_CMD!(),
+        ld = toolchain.ld_lld,
+        script = toolchain.linker_script,
+        input = input,
+        output = output
+    );
+
+    toolchain.run("LNK", link_cmd)
+}
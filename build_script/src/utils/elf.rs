@@ -0,0 +1,96 @@
+//! Relocatable ELF32 ARM objects holding a single section
+//!
+//! Enough for host tools to hand a module's bytes to binutils/LLVM tools, e.g.
+//! llvm-objdump, or to link them into firmware.
+
+use object::write::{Object, Symbol, SymbolSection};
+use object::{
+    elf, Architecture, BinaryFormat, Endianness, FileFlags, SectionKind, SymbolFlags, SymbolKind,
+    SymbolScope,
+};
+use std::error::Error;
+
+/// What a symbol of the object names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfSymbolKind {
+    /// no type, e.g. ARM mapping symbols ($t, $d)
+    NoType,
+    Object,
+    /// a function, Thumb functions have bit 0 of their value set
+    Func,
+}
+
+/// A symbol defined in the data section of the object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSymbol {
+    pub name: String,
+    /// offset in the section
+    pub value: u32,
+    pub size: u32,
+    pub kind: ElfSymbolKind,
+    pub global: bool,
+}
+
+impl ElfSymbol {
+    /// a local symbol without type or size, like the ARM mapping symbols
+    pub fn label(name: &str, value: u32) -> ElfSymbol {
+        ElfSymbol {
+            name: String::from(name),
+            value,
+            size: 0,
+            kind: ElfSymbolKind::NoType,
+            global: false,
+        }
+    }
+}
+
+/// The single section of the object
+#[derive(Debug, Clone)]
+pub struct ElfSection<'a> {
+    pub name: &'a str,
+    pub data: &'a [u8],
+    /// the section holds code rather than data
+    pub executable: bool,
+    pub align: u32,
+}
+
+/// Build a relocatable object holding `section`, with `symbols` defined in it
+pub fn relocatable_object(
+    section: &ElfSection,
+    symbols: &[ElfSymbol],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut object = Object::new(BinaryFormat::Elf, Architecture::Arm, Endianness::Little);
+    object.flags = FileFlags::Elf {
+        os_abi: elf::ELFOSABI_NONE,
+        abi_version: 0,
+        e_flags: elf::EF_ARM_EABI_VER5,
+    };
+    let kind = if section.executable {
+        SectionKind::Text
+    } else {
+        SectionKind::ReadOnlyData
+    };
+    let id = object.add_section(Vec::new(), section.name.as_bytes().to_vec(), kind);
+    object.set_section_data(id, section.data.to_vec(), u64::from(section.align));
+    for symbol in symbols {
+        object.add_symbol(Symbol {
+            name: symbol.name.as_bytes().to_vec(),
+            value: u64::from(symbol.value),
+            size: u64::from(symbol.size),
+            kind: match symbol.kind {
+                ElfSymbolKind::NoType => SymbolKind::Label,
+                ElfSymbolKind::Object => SymbolKind::Data,
+                ElfSymbolKind::Func => SymbolKind::Text,
+            },
+            scope: if symbol.global {
+                SymbolScope::Dynamic
+            } else {
+                SymbolScope::Compilation
+            },
+            weak: false,
+            section: SymbolSection::Section(id),
+            flags: SymbolFlags::None,
+        });
+    }
+    Ok(object.write()?)
+}
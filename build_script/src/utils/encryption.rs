@@ -0,0 +1,100 @@
+//! AES-256-GCM encryption of the stored .text and .data
+//!
+//! The encrypted image has an encryption record:
+//!     key id, nonce (12 bytes), tag (16 bytes)
+//! and .text and .data (compressed first, if at all) are encrypted as one ciphertext, split
+//! over the text and data records. The layout record is authenticated along with it, and
+//! dl-lib asks the firmware's key provider for the key with that id.
+//!
+//! The nonce is derived from the key, the layout and the payload, so the same input gives
+//! the same image while different payloads never share a nonce under one key.
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, Tag};
+use image_format::Encryption;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Key to encrypt images with, dl-lib's key provider must know it under the same id
+#[derive(Clone)]
+pub struct EncryptionKey {
+    pub id: u32,
+    key: [u8; KEY_LEN],
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptionKey {
+    pub fn new(id: u32, key: [u8; KEY_LEN]) -> EncryptionKey {
+        EncryptionKey { id, key }
+    }
+
+    /// read the raw 32-byte AES-256 key from `path`
+    pub fn read(id: u32, path: impl AsRef<Path>) -> Result<EncryptionKey, Box<dyn Error>> {
+        let path = path.as_ref();
+        let key: [u8; KEY_LEN] = fs::read(path)?.try_into().map_err(|_| {
+            format!(
+                "{}: an encryption key is {} raw bytes",
+                path.display(),
+                KEY_LEN
+            )
+        })?;
+        Ok(EncryptionKey::new(id, key))
+    }
+}
+
+/// Encrypt `payload` in place, authenticating `layout` with it,
+/// and return the encryption record
+pub fn encrypt(key: &EncryptionKey, layout: &[u8], payload: &mut Vec<u8>) -> Encryption {
+    let digest = Sha256::new()
+        .chain_update(key.key)
+        .chain_update(layout)
+        .chain_update(&*payload)
+        .finalize();
+    let nonce = Nonce::from_slice(&digest[..NONCE_LEN]);
+    let tag = Aes256Gcm::new(&key.key.into())
+        .encrypt_in_place_detached(nonce, layout, payload)
+        .expect("payload too large for AES-GCM");
+
+    Encryption {
+        key_id: key.id,
+        nonce: (*nonce).into(),
+        tag: tag.into(),
+    }
+}
+
+/// Decrypt `payload`, the encrypted .text and .data of an image, in place,
+/// failing unless `encryption` authenticates it and `layout` under `key`
+pub fn decrypt(
+    key: &EncryptionKey,
+    encryption: &Encryption,
+    layout: &[u8],
+    payload: &mut [u8],
+) -> Result<(), Box<dyn Error>> {
+    if key.id != encryption.key_id {
+        return Err(format!(
+            "image is encrypted with key {}, not with key {}",
+            encryption.key_id, key.id
+        )
+        .into());
+    }
+    Aes256Gcm::new(&key.key.into())
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&encryption.nonce),
+            layout,
+            payload,
+            Tag::from_slice(&encryption.tag),
+        )
+        .map_err(|_| "decryption failed, wrong key or tampered image".into())
+}
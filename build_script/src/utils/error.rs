@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::fmt;
+
+/// Problems with the input objects that prevent building an image
+#[derive(Debug)]
+pub enum BuildError {
+    /// the same global symbol is defined (non-weak) by two input objects
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    /// a pointer in .data to something that is neither in the module nor an external symbol
+    UnsupportedDataPointer { symbol: String, offset: u64 },
+    /// a symbol outside the GOT, .data and .bss addressed relative to R9
+    BadStaticBaseRelocation { symbol: String, offset: u64 },
+    /// a symbol no input object defines addressed relative to R9
+    ExternalStaticBaseRelocation { symbol: String, object: String },
+    /// a relocation dl-lib can't apply, with where it is and what it points at
+    UnsupportedRelocation {
+        r_type: String,
+        section: String,
+        offset: u64,
+        function: Option<String>,
+        symbol: String,
+        reason: &'static str,
+    },
+    /// an allocated section that can't be placed in the image
+    UnsupportedSection { name: String, reason: &'static str },
+    /// a relocation against a symbol that lives in no part of the image, e.g. an absolute one
+    UnplacedSymbol { symbol: String },
+    /// the linker script doesn't start the RW segment with the GOT followed by .data
+    BadStaticLayout,
+    /// a global function that isn't exported by the linked module, e.g. undefined or local
+    MissingExport { function: String },
+    /// an exported function whose trampoline isn't in .text of the linked module
+    MissingTrampoline { function: String },
+    /// an entry of `.init_array` or `.fini_array` that isn't a Thumb function in .text
+    BadFunctionArrayEntry { section: String, index: usize },
+    /// two symbols the loader can't tell apart, `second` is from `module`
+    SymbolHashCollision {
+        hash: u32,
+        first: String,
+        second: String,
+        module: String,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::DuplicateSymbol {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "duplicate symbol `{}` defined in {} and {}",
+                name, first, second
+            ),
+            BuildError::UnsupportedDataPointer { symbol, offset } => write!(
+                f,
+                "pointer to `{}` at .data+{:#x} can't be relocated at load time",
+                symbol, offset
+            ),
+            BuildError::BadStaticBaseRelocation { symbol, offset } => write!(
+                f,
+                "`{}` is addressed relative to R9 at {:#x} but isn't in .data or .bss",
+                symbol, offset
+            ),
+            BuildError::ExternalStaticBaseRelocation { symbol, object } => write!(
+                f,
+                "`{}` is addressed relative to R9 in {} but isn't defined by the module",
+                symbol, object
+            ),
+            BuildError::UnsupportedRelocation {
+                r_type,
+                section,
+                offset,
+                function,
+                symbol,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "unsupported relocation {} at {}+{:#x}",
+                    r_type, section, offset
+                )?;
+                if let Some(function) = function {
+                    write!(f, " in `{}`", function)?;
+                }
+                write!(f, " against `{}`: {}", symbol, reason)
+            }
+            BuildError::UnsupportedSection { name, reason } => {
+                write!(
+                    f,
+                    "section `{}` can't be placed in the image: {}",
+                    name, reason
+                )
+            }
+            BuildError::UnplacedSymbol { symbol } => write!(
+                f,
+                "`{}` is referenced through the GOT but isn't in .text, .data or .bss",
+                symbol
+            ),
+            BuildError::BadStaticLayout => write!(
+                f,
+                "the RW segment must start with .got (if any) followed by .data and .bss"
+            ),
+            BuildError::MissingExport { function } => write!(
+                f,
+                "global function `{}` isn't exported by the module",
+                function
+            ),
+            BuildError::MissingTrampoline { function } => write!(
+                f,
+                "exported function `{}` has no trampoline in .text",
+                function
+            ),
+            BuildError::BadFunctionArrayEntry { section, index } => write!(
+                f,
+                "entry {} of `{}` isn't a Thumb function in .text",
+                index, section
+            ),
+            BuildError::SymbolHashCollision {
+                hash,
+                first,
+                second,
+                module,
+            } => write!(
+                f,
+                "`{}` has the same hash {:#010x} as `{}` of module {}",
+                first, hash, second, module
+            ),
+        }
+    }
+}
+
+impl Error for BuildError {}
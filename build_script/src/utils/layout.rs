@@ -0,0 +1,237 @@
+//! Mapping of the sections of the linked module onto the parts of the image
+use super::error::BuildError;
+use object::{elf, Object, ObjectSection, ObjectSegment, SectionFlags, SectionIndex, SectionKind};
+use std::error::Error;
+
+/// Part of the image an allocated section ends up in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// code and read-only data, executed in place from flash
+    Text,
+    /// GOT at the start of the RW block, filled by the loader
+    Got,
+    /// initialised statics, copied from the image
+    Data,
+    /// zero-initialised statics, only their size is stored
+    Bss,
+}
+
+/// Where every allocated section of the linked module goes
+///
+/// The distances between sections of the same part are kept as the linker laid them out,
+/// so PC-relative (within .text) and R9-relative (within GOT, .data and .bss) references
+/// resolved at link time stay valid wherever dl-lib places the module.
+#[derive(Debug)]
+pub struct SectionLayout {
+    regions: Vec<(SectionIndex, Region)>,
+    /// link-time address of the first byte of .text
+    pub text_base: u64,
+    /// code and read-only data, gaps between sections zeroed
+    pub text: Vec<u8>,
+    /// link-time value of R9, the start of the GOT or of .data if there is no GOT
+    pub static_base: u64,
+    pub l_got: usize,
+    /// initialised statics, NOBITS sections among them zeroed
+    pub data: Vec<u8>,
+    pub l_bss: usize,
+}
+
+fn is_alloc(section: &object::Section) -> bool {
+    matches!(section.flags(), SectionFlags::Elf { sh_flags } if sh_flags & elf::SHF_ALLOC as u64 != 0)
+}
+
+/// `.init_array` or `.fini_array`, tables of functions the image holds apart from
+/// the sections, see `SectionLayout::function_array`
+pub fn is_function_array(section: &object::Section) -> bool {
+    matches!(
+        section.kind(),
+        SectionKind::Elf(elf::SHT_INIT_ARRAY | elf::SHT_FINI_ARRAY)
+    )
+}
+
+/// which part of the image holds an allocated section
+fn classify(section: &object::Section) -> Result<Region, Box<dyn Error>> {
+    let name = section.name()?;
+    let unsupported = |reason| {
+        Box::new(BuildError::UnsupportedSection {
+            name: String::from(name),
+            reason,
+        })
+    };
+    match section.kind() {
+        SectionKind::Text | SectionKind::ReadOnlyData | SectionKind::ReadOnlyString => {
+            Ok(Region::Text)
+        }
+        SectionKind::Data if name.starts_with(".got") => Ok(Region::Got),
+        SectionKind::Data => Ok(Region::Data),
+        SectionKind::UninitializedData => Ok(Region::Bss),
+        SectionKind::Tls | SectionKind::UninitializedTls => {
+            Err(unsupported("thread-local storage isn't supported"))
+        }
+        _ => Err(unsupported(
+            "no part of the image holds this kind of section",
+        )),
+    }
+}
+
+impl SectionLayout {
+    /// Classify the allocated sections of a linked module and lay out .text and the RW block
+    pub fn new(obj_file: &object::File) -> Result<SectionLayout, Box<dyn Error>> {
+        let mut sections = Vec::new();
+        for section in obj_file.sections() {
+            // empty output sections (e.g. no .data) take no room in the image
+            if is_alloc(&section) && section.size() > 0 && !is_function_array(&section) {
+                sections.push((classify(&section)?, section));
+            }
+        }
+        sections.sort_by_key(|(_, section)| section.address());
+
+        // .text: from the first to the last text section, nothing else in between
+        let text: Vec<_> = sections
+            .iter()
+            .filter(|(region, _)| *region == Region::Text)
+            .collect();
+        let text_base = text.first().map_or(0, |(_, section)| section.address());
+        let text_end = text
+            .last()
+            .map_or(0, |(_, section)| section.address() + section.size());
+        let mut text_bytes = vec![0; (text_end - text_base) as usize];
+        for (_, section) in &text {
+            let start = (section.address() - text_base) as usize;
+            text_bytes[start..start + section.size() as usize].copy_from_slice(section.data()?);
+        }
+
+        // RW block: GOT, then .data and .bss, again without foreign sections in between
+        let rw: Vec<_> = sections
+            .iter()
+            .filter(|(region, _)| *region != Region::Text)
+            .collect();
+        let static_base = rw.first().map_or(0, |(_, section)| section.address());
+        let rw_end = rw
+            .iter()
+            .map(|(_, section)| section.address() + section.size())
+            .max()
+            .unwrap_or(static_base);
+        for (region, section) in &sections {
+            let address = section.address();
+            let inside = |base: u64, end: u64| base <= address && address < end;
+            let misplaced = match region {
+                Region::Text => inside(static_base, rw_end),
+                _ => inside(text_base, text_end),
+            };
+            if misplaced {
+                return Err(Box::new(BuildError::UnsupportedSection {
+                    name: String::from(section.name()?),
+                    reason: "it lies between the sections of another part of the image",
+                }));
+            }
+        }
+
+        let data_start = rw
+            .iter()
+            .find(|(region, _)| *region != Region::Got)
+            .map_or(rw_end, |(_, section)| section.address());
+        // the GOT comes first, R9 must point at it
+        if rw
+            .iter()
+            .any(|(region, section)| *region == Region::Got && section.address() >= data_start)
+        {
+            return Err(Box::new(BuildError::BadStaticLayout));
+        }
+        // lld resolves R9-relative relocations against the first section of the segment
+        if !rw.is_empty()
+            && !rw.iter().all(|(_, section)| {
+                obj_file.segments().any(|segment| {
+                    segment.address() == static_base
+                        && section.address() + section.size() <= segment.address() + segment.size()
+                })
+            })
+        {
+            return Err(Box::new(BuildError::BadStaticLayout));
+        }
+        let data_end = rw
+            .iter()
+            .filter(|(region, _)| *region == Region::Data)
+            .map(|(_, section)| section.address() + section.size())
+            .max()
+            .unwrap_or(data_start);
+        let mut data = vec![0; (data_end - data_start) as usize];
+        for (region, section) in &rw {
+            if *region == Region::Data {
+                let start = (section.address() - data_start) as usize;
+                data[start..start + section.size() as usize].copy_from_slice(section.data()?);
+            }
+        }
+
+        Ok(SectionLayout {
+            regions: sections
+                .iter()
+                .map(|(region, section)| (section.index(), *region))
+                .collect(),
+            text_base,
+            text: text_bytes,
+            static_base,
+            l_got: (data_start - static_base) as usize,
+            data,
+            l_bss: (rw_end.max(data_end) - data_end) as usize,
+        })
+    }
+
+    /// part of the image a section goes to, None for sections that aren't loaded
+    pub fn region(&self, index: SectionIndex) -> Option<Region> {
+        self.regions
+            .iter()
+            .find(|(section, _)| *section == index)
+            .map(|(_, region)| *region)
+    }
+
+    /// sections going to `region`, by address
+    pub fn sections(&self, region: Region) -> impl Iterator<Item = SectionIndex> + '_ {
+        self.regions
+            .iter()
+            .filter(move |(_, r)| *r == region)
+            .map(|(section, _)| *section)
+    }
+
+    /// link-time address of the first byte of .data, .bss follows .data
+    pub fn data_start(&self) -> u64 {
+        self.static_base + self.l_got as u64
+    }
+
+    /// offset of `address` of section `index` into .text, or into .data for statics
+    pub fn offset(&self, index: SectionIndex, address: u64) -> Option<u64> {
+        match self.region(index)? {
+            Region::Text => Some(address.wrapping_sub(self.text_base)),
+            Region::Data | Region::Bss => Some(address.wrapping_sub(self.data_start())),
+            Region::Got => None,
+        }
+    }
+
+    /// The functions listed by the sections of type `sh_type` (SHT_INIT_ARRAY or
+    /// SHT_FINI_ARRAY), in link order, as offsets into .text with the thumb bit
+    pub fn function_array(
+        &self,
+        obj_file: &object::File,
+        sh_type: u32,
+    ) -> Result<Vec<u32>, Box<dyn Error>> {
+        let text_end = self.text_base + self.text.len() as u64;
+        let mut functions = Vec::new();
+        for section in obj_file
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Elf(sh_type))
+        {
+            for (index, word) in section.data()?.chunks(4).enumerate() {
+                // the linker filled in link-time addresses, undefined functions are 0
+                let address = u32::from_le_bytes(word.try_into()?) as u64;
+                if address & 1 == 0 || !(self.text_base..text_end).contains(&address) {
+                    return Err(Box::new(BuildError::BadFunctionArrayEntry {
+                        section: String::from(section.name()?),
+                        index,
+                    }));
+                }
+                functions.push((address - self.text_base) as u32);
+            }
+        }
+        Ok(functions)
+    }
+}
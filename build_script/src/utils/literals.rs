@@ -12,50 +12,64 @@ pub const ASM_TAIL: &str = "
 #[macro_export]
 macro_rules! ASM_CMD {
     () => {
-        r"clang -c {asm} -o {elf} --target=thumbv7em-none-eabi"
+        r"{clang} -c {asm} -o {elf} --target=thumbv7em-none-eabi"
     };
 }
 
+/// Trampoline of exported function `{func}`, switches R9 to the module's static base
+/// around the call and restores the caller's, the loader patches the movw/movt pair
 #[macro_export]
-macro_rules! FUNPRE {
+macro_rules! TRAMPOLINE {
     () => {
         r"
     .thumb_func
-    .align 4
+    .align 2
     .globl {s}
-    .type {s}, %function
-    .extern {modulename}
+    .hidden {s}
     .type {s}, %function
     {s}:
-    push    {{r9, lr}}
-    movw r11, #0
-    movt r11, #0
-    b {modulename}
+    push.w {{r9, lr}}
+    movw r9, #0
+    movt r9, #0
+    bl {func}
+    pop.w {{r9, pc}}
     .size {s}, . - {s}
     "
     };
 }
 
+/// Veneer for a function of another module reached with a plain `bl`/`b.w`,
+/// the call lands here and continues through the GOT slot of `{import}`,
+/// which the loader fills like any other external GOT entry
 #[macro_export]
-macro_rules! OBJPRE {
-    () => { r"
+macro_rules! VENEER {
+    () => {
+        r"
     .thumb_func
-    .align 4
+    .align 2
     .globl {s}
+    .hidden {s}
     .type {s}, %function
     {s}:
-    movw r9, #0
-    movt r9, #0
-    blx r11
-    pop {{r9, pc}}
-    bx lr 
+    ldr r12, 1f
+    ldr r12, [r9, r12]
+    bx r12
+    .align 2
+1:  .word {import}(GOT)
     .size {s}, . - {s}
-    " };
+    "
+    };
 }
 
 #[macro_export]
 macro_rules! LINK_CMD{
     () => {
-        r"ld.lld -Tcode_before_data.ld --unresolved-symbols=ignore-in-object-files --emit-relocs {input} -o {output}"
+        r"{ld} -T{script} -z norelro --unresolved-symbols=ignore-in-object-files --emit-relocs {input} -o {output}"
     };
 }
+
+/// prefix of the symbol a veneer loads its target from, dropped in the image's symbol table
+pub const IMPORT_PREFIX: &str = "__dl_import_";
+
+/// prefix of the trampoline of an exported function, followed by the function name
+pub const TRAMPOLINE_PREFIX: &str = "__dl_trampoline_";
@@ -0,0 +1,132 @@
+//! LZ4 block compression of .text and .data
+//!
+//! Plain LZ4 block format, dl-lib decompresses each block straight into the memory
+//! allocated for its section.
+use std::error::Error;
+
+/// farthest a match may reach back, the largest offset a sequence can store
+pub const MAX_OFFSET: usize = u16::MAX as usize;
+/// shortest match worth encoding
+const MIN_MATCH: usize = 4;
+/// the last 5 bytes are always literals and no match starts in the last 12 bytes,
+/// as required by the LZ4 block format
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+const HASH_BITS: u32 = 12;
+
+fn hash4(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// length beyond what fits in a token nibble, as a run of 255s and a final byte
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    let token = (literals.len().min(15) << 4 | match_len.min(15)) as u8;
+    out.push(token);
+    if literals.len() >= 15 {
+        push_length(out, literals.len() - 15);
+    }
+    out.extend(literals);
+    if let Some((offset, _)) = matched {
+        out.extend((offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            push_length(out, match_len - 15);
+        }
+    }
+}
+
+/// Compress `input` into one LZ4 block, greedy and deterministic
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+    let match_limit = input.len().saturating_sub(MF_LIMIT);
+    while pos < match_limit {
+        let slot = &mut table[hash4(&input[pos..])];
+        let candidate = *slot;
+        *slot = pos;
+        if candidate == usize::MAX
+            || pos - candidate > MAX_OFFSET
+            || input[candidate..candidate + MIN_MATCH] != input[pos..pos + MIN_MATCH]
+        {
+            pos += 1;
+            continue;
+        }
+        let max_len = input.len() - LAST_LITERALS - pos;
+        let len = (0..max_len)
+            .take_while(|i| input[candidate + i] == input[pos + i])
+            .count();
+        push_sequence(&mut out, &input[anchor..pos], Some((pos - candidate, len)));
+        pos += len;
+        anchor = pos;
+    }
+    push_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// Decompress one LZ4 block into exactly `len` bytes, as dl-lib does
+pub fn decompress(input: &[u8], len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let truncated = || "LZ4 block ends early";
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    // a run of 255s and a final byte adds to a nibble of 15
+    let read_length = |pos: &mut usize, nibble: usize| -> Result<usize, Box<dyn Error>> {
+        let mut len = nibble;
+        if nibble == 15 {
+            loop {
+                let byte = *input.get(*pos).ok_or_else(truncated)?;
+                *pos += 1;
+                len += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    };
+    while pos < input.len() {
+        let token = input[pos] as usize;
+        pos += 1;
+        let literals = read_length(&mut pos, token >> 4)?;
+        let end = pos.checked_add(literals).ok_or_else(truncated)?;
+        out.extend(input.get(pos..end).ok_or_else(truncated)?);
+        pos = end;
+        // the last sequence has no match
+        if pos == input.len() {
+            break;
+        }
+        let offset =
+            u16::from_le_bytes(input.get(pos..pos + 2).ok_or_else(truncated)?.try_into()?) as usize;
+        pos += 2;
+        let match_len = read_length(&mut pos, token & 15)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() {
+            return Err("LZ4 match reaches before the start of the output".into());
+        }
+        if match_len > len.saturating_sub(out.len()) {
+            break;
+        }
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != len {
+        return Err(format!(
+            "LZ4 block decompresses to {} bytes instead of {}",
+            out.len(),
+            len
+        )
+        .into());
+    }
+    Ok(out)
+}
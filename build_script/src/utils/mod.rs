@@ -1,4 +1,11 @@
+pub mod elf;
+pub mod encryption;
+pub mod error;
+pub mod layout;
 pub mod literals;
+pub mod lz4;
 pub mod readelf;
 pub mod relocations;
+pub mod signing;
+pub mod symbol_map;
 pub mod symbols;
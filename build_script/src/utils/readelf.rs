@@ -1,4 +1,7 @@
+use super::error::BuildError;
+use super::relocations;
 use object::{Object, ObjectSymbol};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 
@@ -27,3 +30,80 @@ pub fn get_pub_funcs(obj: &str) -> Result<Vec<String>, Box<dyn Error>> {
     }
     Ok(results)
 }
+
+/// Check that no global symbol is defined by more than one of the given objects.
+/// Weak definitions may be overridden and are not reported.
+pub fn check_duplicate_definitions(objs: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut defined_in: HashMap<String, &String> = HashMap::new();
+    for obj in objs {
+        let bin_data = fs::read(obj)?;
+        let obj_file = object::File::parse(&*bin_data)?;
+        for sym in obj_file.symbols() {
+            if !sym.is_global() || sym.is_undefined() || sym.is_weak() {
+                continue;
+            }
+            let name = sym.name()?.to_string();
+            if let Some(first) = defined_in.get(&name) {
+                return Err(Box::new(BuildError::DuplicateSymbol {
+                    name,
+                    first: first.to_string(),
+                    second: obj.clone(),
+                }));
+            }
+            defined_in.insert(name, obj);
+        }
+    }
+    Ok(())
+}
+
+/// Names of the global symbols defined by any of the given objects
+fn defined_symbols(objs: &[String]) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut defined: HashSet<String> = HashSet::new();
+    for obj in objs {
+        let bin_data = fs::read(obj)?;
+        let obj_file = object::File::parse(&*bin_data)?;
+        for sym in obj_file.symbols() {
+            if sym.is_global() && !sym.is_undefined() {
+                defined.insert(sym.name()?.to_string());
+            }
+        }
+    }
+    Ok(defined)
+}
+
+/// Check that every symbol addressed relative to R9 is defined by one of the given objects.
+/// R9 is this module's static base, another module's data can't be reached from it.
+pub fn check_static_base_relocations(objs: &[String]) -> Result<(), Box<dyn Error>> {
+    let defined = defined_symbols(objs)?;
+    for obj in objs {
+        let relocs = relocations::read_relocations(obj)?;
+        for reloc in relocations::get_sbrel_relocations(&relocs) {
+            if reloc.sym_section == 0 && !defined.contains(&reloc.name) {
+                return Err(Box::new(BuildError::ExternalStaticBaseRelocation {
+                    symbol: reloc.name.clone(),
+                    object: obj.clone(),
+                }));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Functions of other modules called with a plain `bl`/`b.w` rather than through the GOT
+/// (objects built without `+long-calls`), in order of first use
+pub fn get_called_imports(objs: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let defined = defined_symbols(objs)?;
+    let mut imports: Vec<String> = Vec::new();
+    for obj in objs {
+        let relocs = relocations::read_relocations(obj)?;
+        for reloc in relocations::get_call_relocations(&relocs) {
+            if reloc.sym_section == 0
+                && !defined.contains(&reloc.name)
+                && !imports.contains(&reloc.name)
+            {
+                imports.push(reloc.name.clone());
+            }
+        }
+    }
+    Ok(imports)
+}
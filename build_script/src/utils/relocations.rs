@@ -6,93 +6,207 @@ use std::error::Error;
 use std::fs;
 
 #[derive(Debug, Clone)]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum RelocationType {
+    /// absolute address: ABS32, TARGET1
+    ABS32,
+    /// `bl`/`b.w`: THM_CALL, THM_JUMP24
     CALL,
     GOT32,
+    /// relative to the static base R9: SBREL32 and the MOVW/MOVT_BREL family
+    SBREL,
+    /// relative to the place, resolved by the linker: branches, literal loads, `adr`, PREL31
+    PCREL,
+    /// nothing to do: NONE, V4BX
     NONE,
+    /// anything else, dl-lib has no way to apply it
+    UNKNOWN,
 }
 
 fn get_relocation_type(r_type: u32) -> RelocationType {
     match r_type {
+        2 | 38 => RelocationType::ABS32,
         26 => RelocationType::GOT32,
-        10 => RelocationType::CALL,
-        _ => RelocationType::NONE,
-        // panic!("Unknown relocation type")
+        9 | 84..=89 => RelocationType::SBREL,
+        10 | 30 => RelocationType::CALL,
+        3 | 11 | 42 | 45 | 46 | 49..=54 | 102 | 103 => RelocationType::PCREL,
+        0 | 40 => RelocationType::NONE,
+        _ => RelocationType::UNKNOWN,
     }
 }
 
+/// ELF names of the ARM relocation types that show up in Thumb code
+const RELOCATION_NAMES: &[(u32, &str)] = &[
+    (0, "R_ARM_NONE"),
+    (1, "R_ARM_PC24"),
+    (2, "R_ARM_ABS32"),
+    (3, "R_ARM_REL32"),
+    (9, "R_ARM_SBREL32"),
+    (10, "R_ARM_THM_CALL"),
+    (11, "R_ARM_THM_PC8"),
+    (24, "R_ARM_GOTOFF32"),
+    (25, "R_ARM_BASE_PREL"),
+    (26, "R_ARM_GOT_BREL"),
+    (27, "R_ARM_PLT32"),
+    (28, "R_ARM_CALL"),
+    (29, "R_ARM_JUMP24"),
+    (30, "R_ARM_THM_JUMP24"),
+    (38, "R_ARM_TARGET1"),
+    (40, "R_ARM_V4BX"),
+    (41, "R_ARM_TARGET2"),
+    (42, "R_ARM_PREL31"),
+    (43, "R_ARM_MOVW_ABS_NC"),
+    (44, "R_ARM_MOVT_ABS"),
+    (45, "R_ARM_MOVW_PREL_NC"),
+    (46, "R_ARM_MOVT_PREL"),
+    (47, "R_ARM_THM_MOVW_ABS_NC"),
+    (48, "R_ARM_THM_MOVT_ABS"),
+    (49, "R_ARM_THM_MOVW_PREL_NC"),
+    (50, "R_ARM_THM_MOVT_PREL"),
+    (51, "R_ARM_THM_JUMP19"),
+    (52, "R_ARM_THM_JUMP6"),
+    (53, "R_ARM_THM_ALU_PREL_11_0"),
+    (54, "R_ARM_THM_PC12"),
+    (84, "R_ARM_MOVW_BREL_NC"),
+    (85, "R_ARM_MOVT_BREL"),
+    (86, "R_ARM_MOVW_BREL"),
+    (87, "R_ARM_THM_MOVW_BREL_NC"),
+    (88, "R_ARM_THM_MOVT_BREL"),
+    (89, "R_ARM_THM_MOVW_BREL"),
+    (96, "R_ARM_GOT_PREL"),
+    (102, "R_ARM_THM_JUMP11"),
+    (103, "R_ARM_THM_JUMP8"),
+];
+
+/// ELF name of a relocation type, e.g. R_ARM_ABS32
+pub fn relocation_name(r_type: u32) -> String {
+    match RELOCATION_NAMES.iter().find(|(t, _)| *t == r_type) {
+        Some((_, name)) => String::from(*name),
+        None => format!("relocation type {}", r_type),
+    }
+}
+
+/// Relocation type from its ELF name (with or without the R_ARM_ prefix) or its number
+pub fn parse_relocation_type(name: &str) -> Option<u32> {
+    if let Ok(r_type) = name.parse() {
+        return Some(r_type);
+    }
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("R_ARM_").unwrap_or(&name);
+    RELOCATION_NAMES
+        .iter()
+        .find(|(_, known)| known[6..] == *name)
+        .map(|(r_type, _)| *r_type)
+}
+
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct Relocation {
     pub r_offset: u32,
     pub r_value: u32,
     pub r_info: u32,
     pub r_type: RelocationType,
     pub name: String,
+    /// index of the target symbol in the ELF symbol table
+    pub sym_index: usize,
+    /// section index of the target symbol, 0 if it's undefined
+    pub sym_section: usize,
+    /// index of the section the relocation applies to
+    pub section: usize,
+}
+
+impl Relocation {
+    /// the ELF relocation type, r_type only keeps its class
+    pub fn raw_type(&self) -> u32 {
+        self.r_info & 0xff
+    }
+}
+
+/// The relocations resolved by dl-lib through the GOT
+/// Where only GOT32 relocations are considered known
+pub fn get_known_relocations(relocs: &[Relocation]) -> impl Iterator<Item = &Relocation> {
+    relocs
+        .iter()
+        .filter(|r| matches!(r.r_type, RelocationType::GOT32))
 }
 
-/// For a given object file, return a vector of known Relocations
-/// Where MOVTW_BREL_NC, MOVT_BREL, ABS32 are considered known
-pub fn get_known_relocations(obj_path: &String) -> Result<Vec<Relocation>, Box<dyn Error>> {
+/// The ABS32 relocations applying to section `section`, e.g. pointers stored in .data
+pub fn get_abs_relocations(
+    relocs: &[Relocation],
+    section: usize,
+) -> impl Iterator<Item = &Relocation> {
+    relocs
+        .iter()
+        .filter(move |r| matches!(r.r_type, RelocationType::ABS32) && r.section == section)
+}
+
+/// The `bl`/`b.w` relocations (THM_CALL, THM_JUMP24)
+pub fn get_call_relocations(relocs: &[Relocation]) -> impl Iterator<Item = &Relocation> {
+    relocs
+        .iter()
+        .filter(|r| matches!(r.r_type, RelocationType::CALL))
+}
+
+/// The relocations addressing a symbol relative to R9
+pub fn get_sbrel_relocations(relocs: &[Relocation]) -> impl Iterator<Item = &Relocation> {
+    relocs
+        .iter()
+        .filter(|r| matches!(r.r_type, RelocationType::SBREL))
+}
+
+/// For a given object file, return all its relocations
+pub fn read_relocations(obj_path: &str) -> Result<Vec<Relocation>, Box<dyn Error>> {
     let file = fs::File::open(obj_path)?;
-    let data = match unsafe { memmap2::Mmap::map(&file) } {
-        Ok(mmap) => mmap,
-        Err(err) => {
-            return Err(Box::new(err));
-        }
-    };
-    let elf = FileHeader32::<Endianness>::parse(&*data)?;
+    let data = unsafe { memmap2::Mmap::map(&file) }?;
+    get_relocations(&data)
+}
+
+/// All relocations of the ELF object `data`, parse it once and filter with the functions above
+pub fn get_relocations(data: &[u8]) -> Result<Vec<Relocation>, Box<dyn Error>> {
+    let elf = FileHeader32::<Endianness>::parse(data)?;
     let endian = elf.endian()?;
-    let sections = elf.sections(endian, &*data)?;
+    let sections = elf.sections(endian, data)?;
     let mut vec_relocations: Vec<Relocation> = Vec::new();
-    for (_index, section) in sections.iter().enumerate() {
+    for section in sections.iter() {
         // println!("{:?} {:?}", index, section);
         if let elf::SHT_REL = section.sh_type(endian) {
-            let relocations = section.rel(endian, &*data)?;
-            if let None = relocations {
+            let relocations = section.rel(endian, data)?;
+            let Some((relocations, link)) = relocations else {
                 continue;
-            }
-            let (relocations, link) = relocations.unwrap();
-            let symbols = sections.symbol_table_by_index(endian, &*data, link);
+            };
+            let target_section = section.sh_info(endian) as usize;
+            let symbols = sections.symbol_table_by_index(endian, data, link);
             for relocation in relocations {
                 let r_offset = relocation.r_offset(endian);
                 let r_type = get_relocation_type(relocation.r_type(endian));
                 let r_info = relocation.r_info(endian);
                 let sym = relocation.r_sym(endian);
-                let (name, value) = symbols
+                let (name, value, sym_section) = symbols
                     .and_then(|symbols| {
-                        symbols.symbol(sym as usize).and_then(|symbol| {
-                            Ok((
+                        symbols.symbol(sym as usize).map(|symbol| {
+                            (
                                 symbol.name(endian, symbols.strings()),
                                 symbol.st_value(endian),
-                            ))
+                                symbol.st_shndx(endian) as usize,
+                            )
                         })
                     })
                     .unwrap();
 
                 let name = String::from_utf8(name.unwrap().to_vec()).unwrap();
-                // TODO: change fixed module
-                if !name.ends_with("module") {
-                    vec_relocations.push(Relocation {
-                        r_offset,
-                        r_value: value,
-                        r_type,
-                        r_info,
-                        name,
-                    });
-                }
+                vec_relocations.push(Relocation {
+                    r_offset,
+                    r_value: value,
+                    r_type,
+                    r_info,
+                    name,
+                    sym_index: sym as usize,
+                    sym_section,
+                    section: target_section,
+                });
             }
         }
     }
     // dbg!(&vec_relocations);
-    return Ok(vec_relocations
-        .into_iter()
-        .filter(|r| {
-            if let RelocationType::GOT32 = r.r_type {
-                true
-            } else {
-                false
-            }
-        })
-        .collect::<Vec<_>>());
+    Ok(vec_relocations)
 }
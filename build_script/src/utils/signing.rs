@@ -0,0 +1,35 @@
+//! Ed25519 signature record closing an image
+//!
+//! public key (32 bytes), signature (64 bytes) of every record before it. dl-lib checks it
+//! against its compiled-in trusted keys before loading the module.
+use ed25519_dalek::{Signer, SigningKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use image_format::Signature;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Read a signing key, the raw 32-byte secret (e.g. `head -c 32 /dev/urandom`)
+pub fn read_signing_key(path: impl AsRef<Path>) -> Result<SigningKey, Box<dyn Error>> {
+    let path = path.as_ref();
+    let secret: [u8; SECRET_KEY_LENGTH] = fs::read(path)?.try_into().map_err(|_| {
+        format!(
+            "{}: a signing key is {} raw bytes",
+            path.display(),
+            SECRET_KEY_LENGTH
+        )
+    })?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// the public key to compile into dl-lib's trusted keys
+pub fn public_key(key: &SigningKey) -> [u8; PUBLIC_KEY_LENGTH] {
+    key.verifying_key().to_bytes()
+}
+
+/// signature record over `records`, the image without its prefix
+pub fn sign(records: &[u8], key: &SigningKey) -> Signature {
+    Signature {
+        public_key: public_key(key),
+        signature: key.sign(records).to_bytes(),
+    }
+}
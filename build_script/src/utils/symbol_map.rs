@@ -0,0 +1,129 @@
+//! Host-side map from the 32-bit symbol hashes dl-lib identifies symbols by to their names
+//!
+//! One line per symbol, after a comment line naming the module:
+//!     export 1b6e2c0d test
+//!     import 0b8866b2 adc
+//!
+//! Images built with hashed names carry no names at all, tools read them from the map.
+//! Maps of the dependencies also let build_script catch hash collisions between modules.
+use super::error::BuildError;
+use image_format::gnu_hash;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Hashes and names of the symbols a module exports and imports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolMap {
+    pub module: String,
+    pub exports: Vec<(u32, String)>,
+    pub imports: Vec<(u32, String)>,
+}
+
+fn hashed(names: &[String]) -> Vec<(u32, String)> {
+    names
+        .iter()
+        .map(|name| (gnu_hash(name.as_bytes()), name.clone()))
+        .collect()
+}
+
+impl SymbolMap {
+    pub fn new(module: &str, exports: &[String], imports: &[String]) -> SymbolMap {
+        SymbolMap {
+            module: String::from(module),
+            exports: hashed(exports),
+            imports: hashed(imports),
+        }
+    }
+
+    /// read a map written by `write`
+    pub fn read(path: impl AsRef<Path>) -> Result<SymbolMap, Box<dyn Error>> {
+        let path = path.as_ref();
+        SymbolMap::parse(&fs::read_to_string(path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolMap, Box<dyn Error>> {
+        let mut map = SymbolMap {
+            module: String::new(),
+            exports: Vec::new(),
+            imports: Vec::new(),
+        };
+        for (n, line) in text.lines().enumerate() {
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some(module) = comment.trim().strip_prefix("symbol map of module ") {
+                    map.module = String::from(module);
+                }
+                continue;
+            }
+            let bad_line = || format!("line {}: expected `export|import HASH NAME`", n + 1);
+            let mut fields = line.split_whitespace();
+            let (Some(kind), Some(hash), Some(name), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Err(bad_line().into());
+            };
+            let hash = u32::from_str_radix(hash, 16).map_err(|_| bad_line())?;
+            let entry = (hash, String::from(name));
+            match kind {
+                "export" => map.exports.push(entry),
+                "import" => map.imports.push(entry),
+                _ => return Err(bad_line().into()),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// name of the symbol with hash `hash`, exported or imported
+    pub fn name(&self, hash: u32) -> Option<&str> {
+        self.exports
+            .iter()
+            .chain(&self.imports)
+            .find(|(h, _)| *h == hash)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Check that dl-lib tells every symbol of this module apart by its hash,
+    /// from each other and from what the `dependencies` export
+    pub fn check_collisions(&self, dependencies: &[SymbolMap]) -> Result<(), BuildError> {
+        let own = self.exports.iter().chain(&self.imports);
+        let others = dependencies
+            .iter()
+            .flat_map(|dep| dep.exports.iter().map(move |export| (export, &dep.module)))
+            .chain(own.clone().map(|symbol| (symbol, &self.module)));
+        for (hash, name) in own {
+            for ((other_hash, other), module) in others.clone() {
+                if hash == other_hash && name != other {
+                    return Err(BuildError::SymbolHashCollision {
+                        hash: *hash,
+                        first: name.clone(),
+                        second: other.clone(),
+                        module: module.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# symbol map of module {}", self.module)?;
+        for (kind, symbols) in [("export", &self.exports), ("import", &self.imports)] {
+            for (hash, name) in symbols {
+                writeln!(f, "{} {:08x} {}", kind, hash, name)?;
+            }
+        }
+        Ok(())
+    }
+}
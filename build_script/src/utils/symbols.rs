@@ -1,14 +1,23 @@
-use object::{ObjectSymbol, Symbol};
+use object::{ObjectSymbol, SectionIndex, Symbol};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SymbolType {
     Exported,
     Local,
     External,
 }
 
-#[allow(dead_code)]
-pub fn get_symbol_type(symbol: Symbol) -> Option<SymbolType> {
+/// A symbol of the linked module, keyed by its index in the ELF symbol table rather than
+/// its name, so that local symbols with the same name from different objects stay apart
+#[derive(Debug)]
+pub struct ModuleSymbol {
+    pub name: String,
+    pub symbol_type: SymbolType,
+    pub section: Option<SectionIndex>,
+    pub address: u64,
+}
+
+pub fn get_symbol_type(symbol: &Symbol) -> Option<SymbolType> {
     match (symbol.is_global(), symbol.is_undefined(), symbol.kind()) {
         (true, false, _) => Some(SymbolType::Exported),
         (true, _, _) => Some(SymbolType::External),
@@ -0,0 +1,352 @@
+//! ImageBuilder on objects written the way clang writes them for the testcases
+//!
+//! Linking needs `ld.lld` on PATH, run with `cargo test -- --include-ignored`.
+use build_script::utils::{readelf, relocations};
+use build_script::ImageBuilder;
+use image_format::{DataReloc, Entry, Layout, Prefix, Records, RelocTarget, Table};
+use object::write::{Object, Relocation, SectionId, Symbol, SymbolId, SymbolSection};
+use object::{
+    elf, Architecture, BinaryFormat, Endianness, FileFlags, RelocationEncoding, RelocationKind,
+    SectionFlags, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+};
+use std::error::Error;
+use tempfile::TempDir;
+
+/// `movs r0, #0; bx lr`, every function of the objects is one of these
+const FUNCTION: [u8; 4] = [0x00, 0x20, 0x70, 0x47];
+
+/// An object being written, and where its functions are
+struct ObjectFile {
+    object: Object<'static>,
+    text: SectionId,
+}
+
+impl ObjectFile {
+    fn new() -> ObjectFile {
+        let mut object = Object::new(BinaryFormat::Elf, Architecture::Arm, Endianness::Little);
+        object.flags = FileFlags::Elf {
+            os_abi: elf::ELFOSABI_NONE,
+            abi_version: 0,
+            e_flags: elf::EF_ARM_EABI_VER5,
+        };
+        let text = object.add_section(Vec::new(), b".text".to_vec(), SectionKind::Text);
+        ObjectFile { object, text }
+    }
+
+    /// a Thumb function at the end of .text, global or `static`
+    fn function(&mut self, name: &str, global: bool) -> SymbolId {
+        let offset = self.object.append_section_data(self.text, &FUNCTION, 4);
+        self.object.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: offset | 1,
+            size: FUNCTION.len() as u64,
+            kind: SymbolKind::Text,
+            scope: if global {
+                SymbolScope::Dynamic
+            } else {
+                SymbolScope::Compilation
+            },
+            weak: false,
+            section: SymbolSection::Section(self.text),
+            flags: SymbolFlags::None,
+        })
+    }
+
+    /// a global function that another object may define too
+    fn weak_function(&mut self, name: &str) -> SymbolId {
+        let function = self.function(name, true);
+        self.object.symbol_mut(function).weak = true;
+        function
+    }
+
+    /// a Thumb function followed by a literal holding the address of `target`,
+    /// the way code built without ropi loads a pointer
+    fn function_with_pointer(&mut self, name: &str, target: SymbolId) -> SymbolId {
+        let function = self.function(name, true);
+        self.object.symbol_mut(function).size += 4;
+        self.pointer(self.text, target);
+        function
+    }
+
+    /// a section of type `sh_type` holding a pointer to `function`, like the
+    /// `.init_array.<priority>` sections `__attribute__((constructor))` goes to
+    fn function_array(&mut self, name: &str, sh_type: u32, function: SymbolId) {
+        let section = self.object.add_section(
+            Vec::new(),
+            name.as_bytes().to_vec(),
+            SectionKind::Elf(sh_type),
+        );
+        self.object.section_mut(section).flags = SectionFlags::Elf {
+            sh_flags: (elf::SHF_ALLOC | elf::SHF_WRITE) as u64,
+        };
+        self.pointer(section, function);
+    }
+
+    /// a section of kind `kind` holding `bytes`, zero-initialised if `kind` is NOBITS
+    fn section(&mut self, name: &str, kind: SectionKind, bytes: &[u8]) -> SectionId {
+        let section = self
+            .object
+            .add_section(Vec::new(), name.as_bytes().to_vec(), kind);
+        match kind {
+            SectionKind::UninitializedData | SectionKind::UninitializedTls => {
+                self.object
+                    .append_section_bss(section, bytes.len() as u64, 4);
+            }
+            _ => {
+                self.object.append_section_data(section, bytes, 4);
+            }
+        }
+        section
+    }
+
+    /// a word at the end of `section` holding the address of `target`
+    fn pointer(&mut self, section: SectionId, target: SymbolId) {
+        let offset = self.object.append_section_data(section, &[0; 4], 4);
+        self.object
+            .add_relocation(
+                section,
+                Relocation {
+                    offset,
+                    size: 32,
+                    kind: RelocationKind::Absolute,
+                    encoding: RelocationEncoding::Generic,
+                    symbol: target,
+                    addend: 0,
+                },
+            )
+            .unwrap();
+    }
+
+    /// write the object into `dir` and return its path
+    fn write(&self, dir: &TempDir, name: &str) -> String {
+        let path = dir.path().join(format!("{}.o", name));
+        std::fs::write(&path, self.object.write().unwrap()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+}
+
+/// the image of module `name` built from `objects` in `dir`
+fn build(dir: &TempDir, name: &str, objects: &[String]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let image = ImageBuilder::new(name)
+        .objects(objects)
+        .out_dir(dir.path())
+        .build()?;
+    Ok(image.bytes)
+}
+
+/// the payload of record `tag` of `image`, empty if it has none
+fn record(image: &[u8], tag: u32) -> &[u8] {
+    let (_, records) = Prefix::decode(image).unwrap();
+    Records::new(records)
+        .map(Result::unwrap)
+        .find(|record| record.tag == tag)
+        .map_or(&[], |record| record.payload)
+}
+
+/// the entries of the table record `tag` of `image`, empty if it has none
+fn table<T: Entry>(image: &[u8], tag: u32) -> Vec<T> {
+    let table = Table::<T>::new(record(image, tag)).unwrap();
+    table.iter().map(Option::unwrap).collect()
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn constructors_in_priority_order() {
+    // testcase/c/constructors.c: `first` has priority 101, it runs before `init`,
+    // although the default-priority .init_array comes first in the object
+    let mut object = ObjectFile::new();
+    let first = object.function("first", false);
+    let init = object.function("init", false);
+    let fini = object.function("fini", false);
+    object.function("test", true);
+    object.function_array(".init_array", elf::SHT_INIT_ARRAY, init);
+    object.function_array(".init_array.00101", elf::SHT_INIT_ARRAY, first);
+    object.function_array(".fini_array", elf::SHT_FINI_ARRAY, fini);
+    let dir = tempfile::tempdir().unwrap();
+    let image = build(&dir, "constructors", &[object.write(&dir, "constructors")]).unwrap();
+    // offsets into .text with the thumb bit, .text starts with the object's
+    assert_eq!(
+        table::<u32>(&image, image_format::RECORD_INIT_ARRAY),
+        [1, 5]
+    );
+    assert_eq!(table::<u32>(&image, image_format::RECORD_FINI_ARRAY), [9]);
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn destructors_in_reverse_order() {
+    // destructors run in reverse priority order, the one of priority 101 last
+    let mut object = ObjectFile::new();
+    let fini_101 = object.function("fini_101", false);
+    let fini_200 = object.function("fini_200", false);
+    let fini = object.function("fini", false);
+    object.function("test", true);
+    object.function_array(".fini_array", elf::SHT_FINI_ARRAY, fini);
+    object.function_array(".fini_array.00200", elf::SHT_FINI_ARRAY, fini_200);
+    object.function_array(".fini_array.00101", elf::SHT_FINI_ARRAY, fini_101);
+    let dir = tempfile::tempdir().unwrap();
+    let image = build(&dir, "destructors", &[object.write(&dir, "destructors")]).unwrap();
+    assert!(table::<u32>(&image, image_format::RECORD_INIT_ARRAY).is_empty());
+    assert_eq!(
+        table::<u32>(&image, image_format::RECORD_FINI_ARRAY),
+        [9, 5, 1]
+    );
+}
+
+#[test]
+fn duplicate_definitions_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut first = ObjectFile::new();
+    first.function("test", true);
+    let mut second = ObjectFile::new();
+    second.function("helper", true);
+    second.function("test", true);
+    let objects = [first.write(&dir, "first"), second.write(&dir, "second")];
+    // refused before anything is linked
+    let err = build(&dir, "duplicate", &objects).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "duplicate symbol `test` defined in {} and {}",
+            objects[0], objects[1]
+        )
+    );
+}
+
+#[test]
+fn weak_and_local_definitions_allowed() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut first = ObjectFile::new();
+    first.function("test", true);
+    first.function("helper", false);
+    let mut second = ObjectFile::new();
+    second.weak_function("test");
+    second.function("helper", false);
+    let objects = [first.write(&dir, "first"), second.write(&dir, "second")];
+    readelf::check_duplicate_definitions(&objects).unwrap();
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn objects_linked_into_one_module() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut first = ObjectFile::new();
+    first.function("test", true);
+    first.weak_function("shared");
+    let mut second = ObjectFile::new();
+    second.function("helper", true);
+    second.weak_function("shared");
+    let objects = [first.write(&dir, "first"), second.write(&dir, "second")];
+    let image = ImageBuilder::new("linked")
+        .objects(&objects)
+        .out_dir(dir.path())
+        .build()
+        .unwrap();
+    // a weak function is exported once
+    let mut exports = image.exports.clone();
+    exports.sort();
+    assert_eq!(exports, ["helper", "shared", "test"]);
+    assert_eq!(image.sizes.text, 4 * FUNCTION.len());
+}
+
+#[test]
+fn relocation_types_by_name() {
+    for name in ["R_ARM_V4BX", "r_arm_v4bx", "V4BX", "40"] {
+        assert_eq!(
+            relocations::parse_relocation_type(name),
+            Some(40),
+            "{}",
+            name
+        );
+    }
+    assert_eq!(relocations::parse_relocation_type("R_ARM_NOPE"), None);
+    assert_eq!(relocations::relocation_name(2), "R_ARM_ABS32");
+    assert_eq!(relocations::relocation_name(200), "relocation type 200");
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn absolute_address_in_text_refused() {
+    let mut object = ObjectFile::new();
+    let test = object.function("test", true);
+    object.function_with_pointer("load_test", test);
+    let dir = tempfile::tempdir().unwrap();
+    let objects = [object.write(&dir, "absolute")];
+    let err = build(&dir, "absolute", &objects).unwrap_err();
+    // the linker keeps relocations against local symbols of .text as ones against .text
+    assert_eq!(
+        err.to_string(),
+        "unsupported relocation R_ARM_ABS32 at .text+0x8 in `load_test` against `.text`: \
+         absolute address in .text, which is executed in place"
+    );
+    // unless the type is allowed
+    let abs32 = relocations::parse_relocation_type("R_ARM_ABS32").unwrap();
+    ImageBuilder::new("absolute")
+        .objects(&objects)
+        .out_dir(dir.path())
+        .allow_relocation(abs32)
+        .build()
+        .unwrap();
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn pointers_in_data_relocated() {
+    let mut object = ObjectFile::new();
+    let test = object.function("test", true);
+    let data = object
+        .object
+        .add_section(Vec::new(), b".data".to_vec(), SectionKind::Data);
+    object.pointer(data, test);
+    let dir = tempfile::tempdir().unwrap();
+    let image = build(&dir, "pointer", &[object.write(&dir, "pointer")]).unwrap();
+    // the word in .data holds the offset into .text the loader adds the base of .text to
+    assert_eq!(
+        table::<DataReloc>(&image, image_format::RECORD_DATA_RELOCS),
+        [DataReloc {
+            offset: 0,
+            target: RelocTarget::Text
+        }]
+    );
+    assert_eq!(
+        record(&image, image_format::RECORD_DATA),
+        1u32.to_le_bytes()
+    );
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn sections_mapped_by_flags() {
+    let mut object = ObjectFile::new();
+    object.function("test", true);
+    object.section(".rodata.table", SectionKind::ReadOnlyData, &[1, 2, 3, 4]);
+    object.section(".data", SectionKind::Data, &[5, 0, 0, 0]);
+    object.section(".bss", SectionKind::UninitializedData, &[0; 8]);
+    // no rule of the linker script names it, its flags make it .data
+    object.section(".module_state", SectionKind::Data, &[6, 0, 0, 0]);
+    let dir = tempfile::tempdir().unwrap();
+    let image = build(&dir, "sections", &[object.write(&dir, "sections")]).unwrap();
+    let layout = Layout::decode(record(&image, image_format::RECORD_LAYOUT)).unwrap();
+    let text = record(&image, image_format::RECORD_TEXT);
+    assert_eq!(text[..4], FUNCTION);
+    assert_eq!(text[4..], [1, 2, 3, 4]);
+    eprintln!(
+        "{:?} {:?}",
+        layout,
+        record(&image, image_format::RECORD_DATA)
+    );
+}
+
+#[test]
+#[ignore = "links with ld.lld"]
+fn thread_local_storage_refused() {
+    let mut object = ObjectFile::new();
+    object.function("test", true);
+    object.section(".tbss", SectionKind::UninitializedTls, &[0; 4]);
+    let dir = tempfile::tempdir().unwrap();
+    let err = build(&dir, "tls", &[object.write(&dir, "tls")]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "section `.tbss` can't be placed in the image: thread-local storage isn't supported"
+    );
+}
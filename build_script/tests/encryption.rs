@@ -0,0 +1,140 @@
+//! .text and .data encrypted by build_script and decrypted by dl-lib
+use build_script::utils::encryption::{self as build, EncryptionKey};
+
+#[path = "../../dl-lib/src/utils/encryption.rs"]
+mod encryption;
+#[path = "../../dl-lib/src/utils/image.rs"]
+#[allow(dead_code)]
+mod image;
+
+use encryption::KeyProvider;
+use image::LoadError;
+
+const KEY_ID: u32 = 7;
+const KEY: [u8; 32] = [0x5a; 32];
+const LAYOUT: &[u8] = &[16, 0, 0, 0, 4, 0, 0, 0];
+const PAYLOAD: &[u8] = b"text of the module, then data";
+
+/// firmware knowing the single key `key` under `id`
+struct Keys {
+    id: u32,
+    key: [u8; 32],
+}
+
+impl KeyProvider for Keys {
+    fn key(&self, key_id: u32) -> Option<[u8; 32]> {
+        (key_id == self.id).then_some(self.key)
+    }
+}
+
+/// the encryption record and the payload encrypted with KEY
+fn encrypted() -> (image_format::Encryption, Vec<u8>) {
+    let mut payload = PAYLOAD.to_vec();
+    let encryption = build::encrypt(&EncryptionKey::new(KEY_ID, KEY), LAYOUT, &mut payload);
+    (encryption, payload)
+}
+
+/// what dl-lib makes of `payload` with the keys of `keys`
+fn decrypt(
+    encryption: &image_format::Encryption,
+    aad: &[u8],
+    keys: Option<&Keys>,
+    mut payload: Vec<u8>,
+) -> Result<Vec<u8>, LoadError> {
+    let keys = keys.map(|keys| keys as &dyn KeyProvider);
+    encryption::decrypt(encryption, aad, keys, &mut payload)?;
+    Ok(payload)
+}
+
+const KEYS: Keys = Keys {
+    id: KEY_ID,
+    key: KEY,
+};
+
+#[test]
+fn round_trip() {
+    let (encryption, payload) = encrypted();
+    assert_eq!(encryption.key_id, KEY_ID);
+    assert_ne!(payload, PAYLOAD);
+    assert_eq!(
+        decrypt(&encryption, LAYOUT, Some(&KEYS), payload.clone()),
+        Ok(PAYLOAD.to_vec())
+    );
+    // inspect decrypts on the host the same way
+    let mut host = payload;
+    build::decrypt(
+        &EncryptionKey::new(KEY_ID, KEY),
+        &encryption,
+        LAYOUT,
+        &mut host,
+    )
+    .unwrap();
+    assert_eq!(host, PAYLOAD);
+}
+
+#[test]
+fn same_input_same_nonce() {
+    let (first, _) = encrypted();
+    let (second, _) = encrypted();
+    assert_eq!(first, second);
+    let mut other = b"other text".to_vec();
+    let other = build::encrypt(&EncryptionKey::new(KEY_ID, KEY), LAYOUT, &mut other);
+    assert_ne!(first.nonce, other.nonce);
+}
+
+#[test]
+fn missing_key_refused() {
+    let (encryption, payload) = encrypted();
+    let missing = Err(LoadError::MissingKey { key_id: KEY_ID });
+    assert_eq!(decrypt(&encryption, LAYOUT, None, payload.clone()), missing);
+    let other_id = Keys {
+        id: KEY_ID + 1,
+        key: KEY,
+    };
+    assert_eq!(
+        decrypt(&encryption, LAYOUT, Some(&other_id), payload),
+        missing
+    );
+}
+
+#[test]
+fn wrong_key_refused() {
+    let (encryption, payload) = encrypted();
+    let wrong = Keys {
+        id: KEY_ID,
+        key: [0xa5; 32],
+    };
+    assert_eq!(
+        decrypt(&encryption, LAYOUT, Some(&wrong), payload.clone()),
+        Err(LoadError::DecryptionFailed)
+    );
+    let mut host = payload;
+    let wrong = EncryptionKey::new(KEY_ID, [0xa5; 32]);
+    assert!(build::decrypt(&wrong, &encryption, LAYOUT, &mut host).is_err());
+    let other_id = EncryptionKey::new(KEY_ID + 1, KEY);
+    assert!(build::decrypt(&other_id, &encryption, LAYOUT, &mut host).is_err());
+}
+
+#[test]
+fn tampered_image_refused() {
+    let (encryption, payload) = encrypted();
+    let mut tag = encryption;
+    tag.tag[0] ^= 1;
+    assert_eq!(
+        decrypt(&tag, LAYOUT, Some(&KEYS), payload.clone()),
+        Err(LoadError::DecryptionFailed)
+    );
+    let mut tampered = payload.clone();
+    tampered[3] ^= 1;
+    assert_eq!(
+        decrypt(&encryption, LAYOUT, Some(&KEYS), tampered),
+        Err(LoadError::DecryptionFailed)
+    );
+    // the layout is authenticated along with the payload
+    let mut layout = LAYOUT.to_vec();
+    layout[0] += 4;
+    assert_eq!(
+        decrypt(&encryption, &layout, Some(&KEYS), payload),
+        Err(LoadError::DecryptionFailed)
+    );
+}
@@ -0,0 +1,586 @@
+//! dl-lib's image parser, signature check and linker on images put together record by
+//! record, the way build_script writes them
+use build_script::utils::signing;
+use ed25519_dalek::SigningKey;
+use image_format::{
+    encode_image, encode_table, gnu_hash, hash_table, name_bytes, push_record, DataReloc, Entry,
+    GotReloc, Layout, Records, RelocTarget, SymbolEntry, SymbolKind, Symbols, Trampoline,
+    IMAGE_ARCH, IMAGE_FLAG_HASHED_NAMES, IMAGE_MAGIC, IMAGE_VERSION, TRAMPOLINE_STATIC_BASE,
+    TRAMPOLINE_UNPATCHED,
+};
+
+// dl-lib only builds for the MCU, the parts of the loader that don't touch the
+// hardware are pulled in by path like in dl-lib/fuzz
+#[path = "../../dl-lib/src/utils/image.rs"]
+#[allow(dead_code)]
+mod image;
+#[path = "../../dl-lib/src/utils/link.rs"]
+mod link;
+#[path = "../../dl-lib/src/utils/signature.rs"]
+#[allow(dead_code)]
+mod signature;
+
+use image::{Image, LoadError, Symbol};
+use link::Bases;
+use signature::{SignaturePolicy, SignatureStatus};
+
+const TEXT_BASE: usize = 0x0800_1000;
+const DATA_BASE: usize = 0x2000_0010;
+/// where a dependency defines `dep_fn`
+const DEP_FN: usize = 0x0800_4001;
+
+/// The parts of an image, encoded into records by `image`
+#[derive(Default)]
+struct Parts {
+    text: Vec<u8>,
+    data: Vec<u8>,
+    got: u32,
+    /// kind, in .text, address and name of every symbol
+    symbols: Vec<(SymbolKind, bool, u32, &'static str)>,
+    relocs: Vec<GotReloc>,
+    /// symbol table index of every global function
+    exports: Vec<u32>,
+    data_relocs: Vec<DataReloc>,
+    trampolines: Vec<Trampoline>,
+    /// .text offsets of the constructors and destructors, with the thumb bit
+    init_array: Vec<u32>,
+    fini_array: Vec<u32>,
+    /// store the hashes of the names instead of the names
+    hashed_names: bool,
+}
+
+impl Parts {
+    fn records(&self) -> Vec<u8> {
+        let layout = Layout {
+            text: self.text.len() as u32,
+            data: self.data.len() as u32,
+            bss: 0,
+            got: self.got,
+            flags: if self.hashed_names {
+                IMAGE_FLAG_HASHED_NAMES
+            } else {
+                0
+            },
+        };
+        let mut names = Vec::new();
+        let mut entries = Vec::new();
+        let mut exported = Vec::new();
+        for (i, (kind, in_text, address, name)) in self.symbols.iter().enumerate() {
+            entries.push(SymbolEntry {
+                kind: *kind,
+                in_text: *in_text,
+                name_offset: names.len() as u32,
+                address: *address,
+            });
+            names.extend(name_bytes(name, self.hashed_names));
+            if *kind == SymbolKind::Exported {
+                exported.push((i as u32, *name));
+            }
+        }
+        let mut records = Vec::new();
+        push_record(
+            &mut records,
+            image_format::RECORD_LAYOUT,
+            &layout.to_bytes(),
+        );
+        push_record(&mut records, image_format::RECORD_TEXT, &self.text);
+        push_record(&mut records, image_format::RECORD_DATA, &self.data);
+        push_record(
+            &mut records,
+            image_format::RECORD_SYMBOLS,
+            &Symbols::encode(&entries, &names),
+        );
+        push_record(
+            &mut records,
+            image_format::RECORD_RELOCS,
+            &encode_table(&self.relocs),
+        );
+        push_record(
+            &mut records,
+            image_format::RECORD_EXPORTS,
+            &encode_table(&self.exports),
+        );
+        push_record(
+            &mut records,
+            image_format::RECORD_DATA_RELOCS,
+            &encode_table(&self.data_relocs),
+        );
+        push_record(
+            &mut records,
+            image_format::RECORD_SYMBOL_HASH,
+            &hash_table(&exported),
+        );
+        if !self.trampolines.is_empty() {
+            push_record(
+                &mut records,
+                image_format::RECORD_TRAMPOLINES,
+                &encode_table(&self.trampolines),
+            );
+        }
+        for (tag, functions) in [
+            (image_format::RECORD_INIT_ARRAY, &self.init_array),
+            (image_format::RECORD_FINI_ARRAY, &self.fini_array),
+        ] {
+            if !functions.is_empty() {
+                push_record(&mut records, tag, &encode_table(functions));
+            }
+        }
+        records
+    }
+
+    fn image(&self) -> Vec<u8> {
+        encode_image(&self.records())
+    }
+
+    /// the image closed by a signature record over `signed`, the records of the image
+    /// unless it was tampered with after signing
+    fn signed_image(&self, signed: &Parts, key: &SigningKey) -> Vec<u8> {
+        let signature = signing::sign(&signed.records(), key);
+        let mut records = self.records();
+        push_record(
+            &mut records,
+            image_format::RECORD_SIGNATURE,
+            &signature.to_bytes(),
+        );
+        encode_image(&records)
+    }
+}
+
+/// a function `f` that reaches itself and `dep_fn` of another module through the GOT,
+/// and a pointer 8 bytes into `dep_fn` in .data
+fn calls_dependency() -> Parts {
+    let mut text = Vec::new();
+    // the words the GOT relocations point at hold the GOT slot
+    text.extend(0u32.to_le_bytes());
+    text.extend(4u32.to_le_bytes());
+    Parts {
+        text,
+        data: 8u32.to_le_bytes().to_vec(),
+        got: 8,
+        symbols: vec![
+            (SymbolKind::Exported, true, 0, "f"),
+            (SymbolKind::External, true, 0, "dep_fn"),
+        ],
+        relocs: vec![
+            GotReloc {
+                offset: 0,
+                symbol: 0,
+            },
+            GotReloc {
+                offset: 4,
+                symbol: 1,
+            },
+        ],
+        exports: vec![0],
+        data_relocs: vec![DataReloc {
+            offset: 0,
+            target: RelocTarget::Symbol(1),
+        }],
+        ..Parts::default()
+    }
+}
+
+/// an exported function `f` entered through a trampoline whose movw/movt pair is `pair`
+fn with_trampoline(pair: [u8; 8]) -> Parts {
+    let mut text = vec![0; 24];
+    text[TRAMPOLINE_STATIC_BASE..][..8].copy_from_slice(&pair);
+    Parts {
+        text,
+        symbols: vec![(SymbolKind::Exported, true, 21, "f")],
+        exports: vec![0],
+        trampolines: vec![Trampoline {
+            offset: 0,
+            symbol: 0,
+        }],
+        ..Parts::default()
+    }
+}
+
+const BASES: Bases = Bases {
+    text: TEXT_BASE,
+    data: DATA_BASE,
+};
+
+/// a dependency defining `dep_fn` at DEP_FN, or none at all
+fn dependency(defines_dep_fn: bool) -> impl Fn(&Symbol) -> Option<usize> {
+    move |symbol| (defines_dep_fn && symbol.s_hash == gnu_hash(b"dep_fn")).then_some(DEP_FN)
+}
+
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn resolved_against_dependency() {
+    let bytes = calls_dependency().image();
+    let image = Image::parse(&bytes).unwrap();
+    let symbols = image.symbols();
+    let mut got = [0; 8];
+    link::fill_got(&image, &symbols, BASES, &mut got, dependency(true)).unwrap();
+    assert_eq!(words(&got), [TEXT_BASE as u32, DEP_FN as u32]);
+    let mut data = image.data.to_vec();
+    link::relocate_data(&image, &symbols, BASES, &mut data, dependency(true)).unwrap();
+    assert_eq!(words(&data), [DEP_FN as u32 + 8]);
+}
+
+#[test]
+fn hashed_names_resolved_like_names() {
+    let named = calls_dependency().image();
+    let named = Image::parse(&named).unwrap();
+    let bytes = Parts {
+        hashed_names: true,
+        ..calls_dependency()
+    }
+    .image();
+    let image = Image::parse(&bytes).unwrap();
+    assert!(named.names().is_some());
+    assert_eq!(image.names(), None);
+    let symbols = image.symbols();
+    let hashes = |symbols: &[Symbol]| symbols.iter().map(|s| s.s_hash).collect::<Vec<_>>();
+    assert_eq!(hashes(&symbols), hashes(&named.symbols()));
+    assert_eq!(hashes(&symbols), [gnu_hash(b"f"), gnu_hash(b"dep_fn")]);
+    // exports are found by hash alone
+    let found = image
+        .symbol_hash()
+        .lookup(gnu_hash(b"f"), |i| symbols[i].s_hash == gnu_hash(b"f"));
+    assert_eq!(found, Some(0));
+    assert_eq!(
+        image
+            .symbol_hash()
+            .lookup(gnu_hash(b"dep_fn"), |i| symbols[i].s_hash
+                == gnu_hash(b"dep_fn")),
+        None
+    );
+    let mut got = [0; 8];
+    link::fill_got(&image, &symbols, BASES, &mut got, dependency(true)).unwrap();
+    assert_eq!(words(&got), [TEXT_BASE as u32, DEP_FN as u32]);
+}
+
+#[test]
+fn missing_dependency_refused() {
+    let bytes = calls_dependency().image();
+    let image = Image::parse(&bytes).unwrap();
+    let symbols = image.symbols();
+    let unresolved = Err(LoadError::UnresolvedSymbol { symbol: 1 });
+    let mut got = [0; 8];
+    assert_eq!(
+        link::fill_got(&image, &symbols, BASES, &mut got, dependency(false)),
+        unresolved
+    );
+    let mut data = image.data.to_vec();
+    assert_eq!(
+        link::relocate_data(&image, &symbols, BASES, &mut data, dependency(false)),
+        unresolved
+    );
+}
+
+/// the image of `parts` with the payload of record `tag` replaced, or the record left out
+fn replaced(parts: &Parts, tag: u32, payload: Option<&[u8]>) -> Vec<u8> {
+    let mut records = Vec::new();
+    for record in Records::new(&parts.records()).map(Result::unwrap) {
+        match (record.tag == tag, payload) {
+            (false, _) => push_record(&mut records, record.tag, record.payload),
+            (true, Some(payload)) => push_record(&mut records, tag, payload),
+            (true, None) => {}
+        }
+    }
+    encode_image(&records)
+}
+
+/// the image of `parts` with the record `tag` added at the end
+fn appended(parts: &Parts, tag: u32, payload: &[u8]) -> Vec<u8> {
+    let mut records = parts.records();
+    push_record(&mut records, tag, payload);
+    encode_image(&records)
+}
+
+/// the image with word `index` of its prefix set to `word`
+fn with_prefix_word(mut image: Vec<u8>, index: usize, word: u32) -> Vec<u8> {
+    image[index * 4..][..4].copy_from_slice(&word.to_le_bytes());
+    image
+}
+
+#[test]
+fn bad_prefix_refused() {
+    let image = calls_dependency().image();
+    let len = image.len();
+    assert_eq!(
+        Image::parse(&image[..12]).unwrap_err(),
+        LoadError::Truncated {
+            expected: 20,
+            actual: 12
+        }
+    );
+    assert_eq!(
+        Image::parse(&image[..len - 1]).unwrap_err(),
+        LoadError::Truncated {
+            expected: len,
+            actual: len - 1
+        }
+    );
+    assert_eq!(
+        Image::parse(&with_prefix_word(image.clone(), 0, IMAGE_MAGIC + 1)).unwrap_err(),
+        LoadError::BadMagic(IMAGE_MAGIC + 1)
+    );
+    assert_eq!(
+        Image::parse(&with_prefix_word(image.clone(), 1, IMAGE_VERSION - 1)).unwrap_err(),
+        LoadError::UnsupportedVersion(IMAGE_VERSION - 1)
+    );
+    assert_eq!(
+        Image::parse(&with_prefix_word(image.clone(), 2, IMAGE_ARCH + 1)).unwrap_err(),
+        LoadError::ArchMismatch(IMAGE_ARCH + 1)
+    );
+    let mut corrupted = image;
+    corrupted[len - 1] ^= 1;
+    assert!(matches!(
+        Image::parse(&corrupted).unwrap_err(),
+        LoadError::Corrupted { expected, actual } if expected != actual
+    ));
+}
+
+#[test]
+fn bad_records_refused() {
+    let parts = calls_dependency();
+    assert_eq!(
+        Image::parse(&appended(&parts, image_format::RECORD_DATA, &[0; 4])).unwrap_err(),
+        LoadError::DuplicateRecord(image_format::RECORD_DATA)
+    );
+    assert_eq!(
+        Image::parse(&replaced(&parts, image_format::RECORD_LAYOUT, None)).unwrap_err(),
+        LoadError::MissingRecord(image_format::RECORD_LAYOUT)
+    );
+    // unknown records are skipped unless they are required
+    let unknown = image_format::RECORD_REQUIRED | 100;
+    assert!(Image::parse(&appended(&parts, 100, &[1, 2, 3])).is_ok());
+    assert_eq!(
+        Image::parse(&appended(&parts, unknown, &[1, 2, 3])).unwrap_err(),
+        LoadError::UnsupportedRecord(unknown)
+    );
+    // the signature covers the records before it, nothing may follow
+    let mut records = parts.records();
+    push_record(&mut records, image_format::RECORD_SIGNATURE, &[0; 96]);
+    assert!(Image::parse(&encode_image(&records)).is_ok());
+    push_record(&mut records, 100, &[]);
+    assert_eq!(
+        Image::parse(&encode_image(&records)).unwrap_err(),
+        LoadError::BadLayout
+    );
+}
+
+#[test]
+fn bad_layout_refused() {
+    let parts = calls_dependency();
+    let layout = |text: u32, got: u32, flags: u32| {
+        Layout {
+            text,
+            data: 4,
+            bss: 0,
+            got,
+            flags,
+        }
+        .to_bytes()
+    };
+    let bad = [
+        (layout(8, 8, 4), LoadError::UnsupportedFlags(4)),
+        // .text of another size than the layout says
+        (layout(12, 8, 0), LoadError::BadLayout),
+        // .data behind the GOT not word aligned
+        (layout(8, 6, 0), LoadError::BadLayout),
+    ];
+    for (layout, err) in bad {
+        let image = replaced(&parts, image_format::RECORD_LAYOUT, Some(&layout));
+        assert_eq!(Image::parse(&image).unwrap_err(), err);
+    }
+    let image = replaced(&parts, image_format::RECORD_RELOCS, Some(&[0; 7]));
+    assert_eq!(Image::parse(&image).unwrap_err(), LoadError::BadLayout);
+}
+
+#[test]
+fn table_entries_out_of_range_refused() {
+    let with = |change: fn(&mut Parts)| {
+        let mut parts = calls_dependency();
+        change(&mut parts);
+        Image::parse(&parts.image()).unwrap_err()
+    };
+    assert_eq!(
+        with(|parts| parts.symbols[0].2 = 8),
+        LoadError::SymbolOutOfRange {
+            symbol: 0,
+            address: 8
+        }
+    );
+    assert_eq!(
+        with(|parts| parts.relocs[1].symbol = 2),
+        LoadError::BadSymbolIndex {
+            index: 2,
+            n_symbol: 2
+        }
+    );
+    assert_eq!(
+        with(|parts| parts.exports[0] = 5),
+        LoadError::BadSymbolIndex {
+            index: 5,
+            n_symbol: 2
+        }
+    );
+    assert_eq!(
+        with(|parts| parts.relocs[1].offset = 6),
+        LoadError::RelocOutOfText {
+            offset: 6,
+            l_text: 8
+        }
+    );
+    // the word at the relocation names the GOT slot just past the GOT
+    assert_eq!(
+        with(|parts| parts.text[4] = 8),
+        LoadError::GotIndexOutOfRange {
+            offset: 4,
+            got_index: 8
+        }
+    );
+    assert_eq!(
+        with(|parts| parts.data_relocs[0].offset = 2),
+        LoadError::DataRelocOutOfData {
+            offset: 2,
+            l_data: 4
+        }
+    );
+    assert_eq!(
+        with(|parts| parts.data_relocs[0].target = RelocTarget::Symbol(3)),
+        LoadError::BadSymbolIndex {
+            index: 3,
+            n_symbol: 2
+        }
+    );
+}
+
+#[test]
+fn bad_hash_table_refused() {
+    let parts = calls_dependency();
+    // an entry for a symbol that isn't exported, or past the symbol table
+    for hash in [hash_table(&[(1, "dep_fn")]), hash_table(&[(2, "f")])] {
+        let image = replaced(&parts, image_format::RECORD_SYMBOL_HASH, Some(&hash));
+        assert_eq!(Image::parse(&image).unwrap_err(), LoadError::BadHashTable);
+    }
+    // an entry whose hash isn't the one of the symbol's name
+    let hash = hash_table(&[(0, "g")]);
+    let image = replaced(&parts, image_format::RECORD_SYMBOL_HASH, Some(&hash));
+    assert_eq!(Image::parse(&image).unwrap_err(), LoadError::BadHashTable);
+    let image = replaced(&parts, image_format::RECORD_SYMBOL_HASH, Some(&[0; 3]));
+    assert_eq!(Image::parse(&image).unwrap_err(), LoadError::BadHashTable);
+}
+
+#[test]
+fn trampoline_encoding_checked() {
+    assert!(Image::parse(&with_trampoline(TRAMPOLINE_UNPATCHED).image()).is_ok());
+    // a pair loading something else than R9, or already patched
+    let mut pair = TRAMPOLINE_UNPATCHED;
+    pair[3] = 0x08;
+    let bytes = with_trampoline(pair).image();
+    assert_eq!(
+        Image::parse(&bytes).unwrap_err(),
+        LoadError::BadTrampoline { index: 0 }
+    );
+    let mut pair = TRAMPOLINE_UNPATCHED;
+    pair[2] = 0x10;
+    let bytes = with_trampoline(pair).image();
+    assert_eq!(
+        Image::parse(&bytes).unwrap_err(),
+        LoadError::BadTrampoline { index: 0 }
+    );
+}
+
+/// the result of checking `image` against the public key of `trusted` under `policy`
+fn check_signature(
+    image: &[u8],
+    trusted: &SigningKey,
+    policy: SignaturePolicy,
+) -> Result<SignatureStatus, LoadError> {
+    let image = Image::parse(image).unwrap();
+    signature::check(&image, policy, &[signing::public_key(trusted)])
+}
+
+/// both policies, refusing and warning, give `err` for `image`
+fn assert_refused(image: &[u8], trusted: &SigningKey, err: LoadError) {
+    assert_eq!(
+        check_signature(image, trusted, SignaturePolicy::Require),
+        Err(err.clone())
+    );
+    assert_eq!(
+        check_signature(image, trusted, SignaturePolicy::WarnOnly),
+        Ok(SignatureStatus::Unverified(err))
+    );
+}
+
+#[test]
+fn signed_image_trusted() {
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let parts = calls_dependency();
+    let image = parts.signed_image(&parts, &key);
+    for policy in [SignaturePolicy::Require, SignaturePolicy::WarnOnly] {
+        assert_eq!(
+            check_signature(&image, &key, policy),
+            Ok(SignatureStatus::Trusted)
+        );
+    }
+}
+
+#[test]
+fn unsigned_image_refused() {
+    let key = SigningKey::from_bytes(&[1; 32]);
+    assert_refused(&calls_dependency().image(), &key, LoadError::Unsigned);
+    assert_eq!(SignaturePolicy::default(), SignaturePolicy::Require);
+}
+
+#[test]
+fn tampered_image_refused() {
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let parts = calls_dependency();
+    let mut tampered = calls_dependency();
+    tampered.data[0] ^= 1;
+    let image = tampered.signed_image(&parts, &key);
+    assert_refused(&image, &key, LoadError::BadSignature);
+}
+
+#[test]
+fn untrusted_key_refused() {
+    let trusted = SigningKey::from_bytes(&[1; 32]);
+    let other = SigningKey::from_bytes(&[2; 32]);
+    let parts = calls_dependency();
+    let image = parts.signed_image(&parts, &other);
+    assert_refused(&image, &trusted, LoadError::UntrustedKey);
+}
+
+#[test]
+fn constructors_and_destructors_in_image_order() {
+    // testcase/c/constructors.c as build_script lays it out: `first`, `init` and `fini`
+    // at the start of .text, see tests/builder.rs
+    let parts = Parts {
+        text: vec![0; 16],
+        init_array: vec![1, 5],
+        fini_array: vec![9],
+        ..Parts::default()
+    };
+    let bytes = parts.image();
+    let image = Image::parse(&bytes).unwrap();
+    assert_eq!(image.constructors().collect::<Vec<_>>(), [1, 5]);
+    assert_eq!(image.destructors().collect::<Vec<_>>(), [9]);
+    // ARM code or past .text
+    for offset in [4, 17] {
+        let parts = Parts {
+            text: vec![0; 16],
+            fini_array: vec![offset],
+            ..Parts::default()
+        };
+        assert_eq!(
+            Image::parse(&parts.image()).unwrap_err(),
+            LoadError::FunctionOutOfText {
+                offset: offset as usize,
+                l_text: 16
+            }
+        );
+    }
+}
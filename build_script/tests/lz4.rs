@@ -0,0 +1,195 @@
+//! LZ4 blocks from build_script's compressor decompress to the same bytes with its own
+//! decompressor and with dl-lib's, and malformed blocks are refused by both without
+//! panicking
+use build_script::utils::lz4::{self, MAX_OFFSET};
+use proptest::prelude::*;
+
+// dl-lib only builds for the MCU, its decoder is pulled in by path like in dl-lib/fuzz
+#[path = "../../dl-lib/src/utils/lz4.rs"]
+mod dl_lz4;
+#[path = "../../dl-lib/src/utils/image.rs"]
+#[allow(dead_code)]
+mod image;
+
+/// `len` bytes that don't compress, the same for the same seed
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2654435761) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// a length beyond a token nibble of 15, as a run of 255s and a final byte
+fn push_length(block: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        block.push(255);
+        len -= 255;
+    }
+    block.push(len as u8);
+}
+
+/// one sequence of a block written by hand, `matched` is the offset and length of its match
+fn sequence(block: &mut Vec<u8>, literals: &[u8], matched: Option<(u16, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - 4);
+    block.push((literals.len().min(15) << 4 | match_len.min(15)) as u8);
+    if literals.len() >= 15 {
+        push_length(block, literals.len() - 15);
+    }
+    block.extend(literals);
+    if let Some((offset, _)) = matched {
+        block.extend(offset.to_le_bytes());
+        if match_len >= 15 {
+            push_length(block, match_len - 15);
+        }
+    }
+}
+
+/// `block` decompressed to `len` bytes by both decompressors, which must agree
+fn decompress(block: &[u8], len: usize) -> Option<Vec<u8>> {
+    let ours = lz4::decompress(block, len).ok();
+    let mut out = vec![0; len];
+    let loader = dl_lz4::decompress(block, &mut out).ok().map(|()| out);
+    assert_eq!(ours, loader);
+    ours
+}
+
+/// compress `input`, check that it decompresses back and return the block
+fn round_trip(input: &[u8]) -> Vec<u8> {
+    let block = lz4::compress(input);
+    assert_eq!(decompress(&block, input.len()).as_deref(), Some(input));
+    block
+}
+
+#[test]
+fn empty_input() {
+    assert_eq!(round_trip(&[]), [0]);
+    assert_eq!(decompress(&[], 0), Some(Vec::new()));
+    assert_eq!(decompress(&[0], 1), None);
+}
+
+#[test]
+fn match_at_max_offset() {
+    // zeros in between, so that they don't push the start out of the hash table
+    let mut input = noise(64, 1);
+    input.resize(MAX_OFFSET, 0);
+    let mut unrelated = input.clone();
+    input.extend_from_within(..64);
+    input.extend(noise(16, 2));
+    unrelated.extend(noise(64 + 16, 3));
+    let block = round_trip(&input);
+    // the repeat was found although it is exactly MAX_OFFSET bytes back
+    assert!(block.len() < round_trip(&unrelated).len() - 32);
+}
+
+#[test]
+fn no_match_past_max_offset() {
+    let mut input = noise(MAX_OFFSET + 1, 1);
+    input.extend_from_within(..64);
+    input.extend(noise(16, 2));
+    let block = round_trip(&input);
+    assert!(block.len() > input.len());
+}
+
+#[test]
+fn overlapping_matches() {
+    // a match longer than its offset copies bytes it has just written
+    let block = round_trip(&[0xaa; 1000]);
+    assert!(block.len() < 24);
+    let block = round_trip(&b"abc".repeat(400));
+    assert!(block.len() < 24);
+    let mut input = noise(100, 3);
+    input.extend_from_within(60..);
+    input.extend_from_within(60..);
+    input.extend(noise(16, 4));
+    round_trip(&input);
+}
+
+#[test]
+fn lengths_around_extension_bytes() {
+    // literal and match lengths across the nibble of 15 and the 255s that extend it
+    for len in 0..600 {
+        let mut input = noise(len, len as u32);
+        input.extend([7; 40]);
+        round_trip(&input);
+
+        let mut input = noise(8, len as u32);
+        input.extend(vec![7; len]);
+        input.extend(noise(16, !(len as u32)));
+        round_trip(&input);
+    }
+}
+
+#[test]
+fn offset_to_start_accepted() {
+    let literals = noise(MAX_OFFSET, 5);
+    let mut block = Vec::new();
+    sequence(&mut block, &literals, Some((MAX_OFFSET as u16, 4)));
+    sequence(&mut block, b"tail!", None);
+    let mut expected = literals.clone();
+    expected.extend_from_slice(&literals[..4]);
+    expected.extend(b"tail!");
+    assert_eq!(decompress(&block, expected.len()), Some(expected));
+}
+
+#[test]
+fn offset_before_start_rejected() {
+    for offset in [0, 3, MAX_OFFSET as u16] {
+        let mut block = Vec::new();
+        sequence(&mut block, b"ab", Some((offset, 4)));
+        sequence(&mut block, b"tail!", None);
+        assert_eq!(decompress(&block, 11), None);
+    }
+}
+
+#[test]
+fn truncated_block_rejected() {
+    let mut input = noise(300, 6);
+    input.extend([0x55; 300]);
+    input.extend_from_within(..300);
+    input.extend(noise(20, 7));
+    let block = round_trip(&input);
+    for end in 0..block.len() {
+        assert_eq!(
+            decompress(&block[..end], input.len()),
+            None,
+            "cut at {}",
+            end
+        );
+    }
+}
+
+#[test]
+fn oversized_lengths_rejected() {
+    // a literal length past the end of the block
+    let mut block = vec![0xf0];
+    push_length(&mut block, 100_000);
+    block.extend(b"abc");
+    assert_eq!(decompress(&block, 100), None);
+    // a match far longer than the output
+    let mut block = Vec::new();
+    sequence(&mut block, b"a", Some((1, 1 << 28)));
+    sequence(&mut block, b"tail!", None);
+    assert_eq!(decompress(&block, 100), None);
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_input(input in prop::collection::vec(any::<u8>(), 0..4096)) {
+        round_trip(&input);
+    }
+
+    #[test]
+    fn repetitive_input(input in prop::collection::vec(0u8..4, 0..4096)) {
+        round_trip(&input);
+    }
+
+    #[test]
+    fn arbitrary_block(block in prop::collection::vec(any::<u8>(), 0..256), len in 0usize..2048) {
+        decompress(&block, len);
+    }
+}
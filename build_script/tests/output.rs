@@ -0,0 +1,205 @@
+//! Intel HEX and S-record output checked against records worked out by hand,
+//! ELF output read back with `object`
+use build_script::output::{render, OutputFormat};
+use build_script::utils::elf::{self, ElfSection, ElfSymbol, ElfSymbolKind};
+use object::elf::{
+    FileHeader32, EF_ARM_EABI_VER5, SHF_ALLOC, SHF_EXECINSTR, SHT_SYMTAB, STB_GLOBAL, STB_LOCAL,
+    STT_FILE, STT_FUNC, STT_NOTYPE, STT_OBJECT, STT_SECTION,
+};
+use object::read::elf::{ElfFile32, FileHeader, Sym};
+use object::{Endianness, Object, ObjectSection, ObjectSymbol, SectionFlags};
+
+fn lines(format: OutputFormat, name: &str, bytes: &[u8]) -> Vec<String> {
+    let out = String::from_utf8(render(&format, name, bytes).unwrap()).unwrap();
+    out.lines().map(String::from).collect()
+}
+
+#[test]
+fn intel_hex_records() {
+    let bytes: Vec<u8> = (0..16).collect();
+    assert_eq!(
+        lines(OutputFormat::IntelHex { base: 0x0800_0000 }, "m", &bytes),
+        [
+            ":020000040800F2",
+            ":10000000000102030405060708090A0B0C0D0E0F78",
+            ":00000001FF",
+        ]
+    );
+}
+
+#[test]
+fn intel_hex_crossing_64k() {
+    // the line is split at the boundary and the upper address changes in between
+    let bytes: Vec<u8> = (0..16).collect();
+    assert_eq!(
+        lines(OutputFormat::IntelHex { base: 0x0800_fff8 }, "m", &bytes),
+        [
+            ":020000040800F2",
+            ":08FFF8000001020304050607E5",
+            ":020000040801F1",
+            ":0800000008090A0B0C0D0E0F9C",
+            ":00000001FF",
+        ]
+    );
+}
+
+#[test]
+fn intel_hex_out_of_address_space() {
+    let format = OutputFormat::IntelHex { base: 0xffff_fff0 };
+    assert!(render(&format, "m", &[0; 17]).is_err());
+}
+
+#[test]
+fn s_records() {
+    let bytes: Vec<u8> = (0..20).collect();
+    assert_eq!(
+        lines(
+            OutputFormat::SRecord { base: 0x0800_0000 },
+            "module",
+            &bytes
+        ),
+        [
+            // header with the module name, data, count of data records, start address
+            "S00900006D6F64756C6570",
+            "S31508000000000102030405060708090A0B0C0D0E0F6A",
+            "S309080000101011121398",
+            "S5030002FA",
+            "S70508000000F2",
+        ]
+    );
+}
+
+#[test]
+fn s_record_24_bit_count() {
+    // more data records than S5 counts
+    let bytes = vec![0; 16 << 16];
+    let lines = lines(OutputFormat::SRecord { base: 0x2000_0000 }, "m", &bytes);
+    assert_eq!(lines.len(), 3 + (1 << 16));
+    assert_eq!(lines[lines.len() - 2..], ["S604010000FA", "S70520000000DA"]);
+}
+
+/// name, value, size, st_type and st_bind of every symbol of `object`,
+/// without the null symbol and the file and section symbols
+fn symbols(object: &[u8]) -> Vec<(String, u32, u32, u8, u8)> {
+    let header = FileHeader32::<Endianness>::parse(object).unwrap();
+    let endian = header.endian().unwrap();
+    let sections = header.sections(endian, object).unwrap();
+    let symbols = sections.symbols(endian, object, SHT_SYMTAB).unwrap();
+    symbols
+        .iter()
+        .filter(|symbol| {
+            symbol.st_name(endian) != 0
+                && symbol.st_type() != STT_FILE
+                && symbol.st_type() != STT_SECTION
+        })
+        .map(|symbol| {
+            let name = symbols.symbol_name(endian, symbol).unwrap();
+            (
+                String::from_utf8(name.to_vec()).unwrap(),
+                symbol.st_value(endian),
+                symbol.st_size(endian),
+                symbol.st_type(),
+                symbol.st_bind(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn elf_object_with_image() {
+    let bytes: Vec<u8> = (0..10).collect();
+    let format = OutputFormat::Elf { section: None };
+    let out = render(&format, "module-def", &bytes).unwrap();
+    let file = ElfFile32::<object::Endianness>::parse(&*out).unwrap();
+    assert_eq!(file.architecture(), object::Architecture::Arm);
+    assert!(file.is_little_endian());
+    assert_eq!(
+        file.raw_header().e_flags.get(file.endian()),
+        EF_ARM_EABI_VER5
+    );
+    let section = file.section_by_name(".rodata.module_def").unwrap();
+    assert_eq!(section.data().unwrap(), bytes);
+    assert_eq!(section.align(), 4);
+    assert_eq!(
+        section.flags(),
+        SectionFlags::Elf {
+            sh_flags: SHF_ALLOC as u64
+        }
+    );
+    assert_eq!(
+        symbols(&out),
+        [
+            (
+                String::from("_binary_module_def_start"),
+                0,
+                10,
+                STT_OBJECT,
+                STB_GLOBAL
+            ),
+            (
+                String::from("_binary_module_def_end"),
+                10,
+                0,
+                STT_OBJECT,
+                STB_GLOBAL
+            ),
+        ]
+    );
+    for symbol in file.symbols().filter(|symbol| symbol.is_global()) {
+        assert_eq!(symbol.section_index(), Some(section.index()));
+    }
+}
+
+#[test]
+fn elf_object_in_given_section() {
+    let format = OutputFormat::Elf {
+        section: Some(String::from(".modules")),
+    };
+    let out = render(&format, "m", &[0; 8]).unwrap();
+    let file = ElfFile32::<object::Endianness>::parse(&*out).unwrap();
+    assert!(file.section_by_name(".modules").is_some());
+    assert!(file.section_by_name(".rodata.m").is_none());
+}
+
+#[test]
+fn executable_section_with_functions() {
+    // how inspect hands .text to llvm-objdump: mapping symbols and Thumb functions
+    let text = [0x00, 0x20, 0x70, 0x47, 0, 0, 0, 0];
+    let out = elf::relocatable_object(
+        &ElfSection {
+            name: ".text",
+            data: &text,
+            executable: true,
+            align: 4,
+        },
+        &[
+            ElfSymbol::label("$t", 0),
+            ElfSymbol {
+                name: String::from("test"),
+                value: 1,
+                size: 4,
+                kind: ElfSymbolKind::Func,
+                global: true,
+            },
+            ElfSymbol::label("$d", 4),
+        ],
+    )
+    .unwrap();
+    let file = ElfFile32::<object::Endianness>::parse(&*out).unwrap();
+    let section = file.section_by_name(".text").unwrap();
+    assert_eq!(section.data().unwrap(), text);
+    assert_eq!(
+        section.flags(),
+        SectionFlags::Elf {
+            sh_flags: (SHF_ALLOC | SHF_EXECINSTR) as u64
+        }
+    );
+    assert_eq!(
+        symbols(&out),
+        [
+            (String::from("$t"), 0, 0, STT_NOTYPE, STB_LOCAL),
+            (String::from("$d"), 4, 0, STT_NOTYPE, STB_LOCAL),
+            (String::from("test"), 1, 4, STT_FUNC, STB_GLOBAL),
+        ]
+    );
+}
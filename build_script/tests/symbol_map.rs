@@ -0,0 +1,66 @@
+//! Symbol map files and the hash collisions they let build_script catch
+use build_script::utils::error::BuildError;
+use build_script::SymbolMap;
+use image_format::gnu_hash;
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| String::from(*name)).collect()
+}
+
+#[test]
+fn written_map_read_back() {
+    let map = SymbolMap::new("app", &names(&["test", "helper"]), &names(&["adc"]));
+    assert_eq!(map.exports[0], (gnu_hash(b"test"), String::from("test")));
+    let text = map.to_string();
+    assert!(text.starts_with("# symbol map of module app\n"));
+    assert!(text.contains(&format!("import {:08x} adc\n", gnu_hash(b"adc"))));
+    assert_eq!(SymbolMap::parse(&text).unwrap(), map);
+    assert_eq!(map.name(gnu_hash(b"adc")), Some("adc"));
+    assert_eq!(map.name(gnu_hash(b"other")), None);
+}
+
+#[test]
+fn malformed_lines_refused() {
+    for text in [
+        "export test",
+        "export 1234 test extra",
+        "export xyz test",
+        "define 1234 test",
+    ] {
+        let err = SymbolMap::parse(text).unwrap_err();
+        assert!(err.to_string().starts_with("line 1:"), "{}", err);
+    }
+    // blank lines and comments are skipped
+    assert!(SymbolMap::parse("\n# comment\n  \n").is_ok());
+}
+
+#[test]
+fn colliding_names_refused() {
+    // djb2 gives "ab" and "bA" the same hash
+    assert_eq!(gnu_hash(b"ab"), gnu_hash(b"bA"));
+    let map = SymbolMap::new("app", &names(&["ab"]), &names(&["bA"]));
+    match map.check_collisions(&[]) {
+        Err(BuildError::SymbolHashCollision {
+            hash,
+            first,
+            second,
+            module,
+        }) => {
+            assert_eq!(hash, gnu_hash(b"ab"));
+            assert_eq!((first.as_str(), second.as_str()), ("ab", "bA"));
+            assert_eq!(module, "app");
+        }
+        other => panic!("expected a collision, got {:?}", other),
+    }
+    // against what a dependency exports
+    let app = SymbolMap::new("app", &names(&["test"]), &names(&["ab"]));
+    let lib = SymbolMap::new("lib", &names(&["bA"]), &[]);
+    assert!(matches!(
+        app.check_collisions(&[lib]),
+        Err(BuildError::SymbolHashCollision { module, .. }) if module == "lib"
+    ));
+    // the same symbol imported from the dependency that exports it
+    let app = SymbolMap::new("app", &names(&["test"]), &names(&["ab"]));
+    let lib = SymbolMap::new("lib", &names(&["ab"]), &[]);
+    assert!(app.check_collisions(&[lib]).is_ok());
+}
@@ -0,0 +1,87 @@
+//! Fuzz the image parser dl-lib runs before allocating a module.
+//!
+//! The parser is pulled in by path since dl-lib itself only builds for the MCU,
+//! the image-format crate it builds on is a regular dependency.
+//! Run with `cargo fuzz run parse_image` from dl-lib/.
+#![no_main]
+use image_format::crc;
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/utils/image.rs"]
+#[allow(dead_code)]
+mod image;
+#[path = "../../src/utils/lz4.rs"]
+mod lz4;
+
+/// offset and end of the crc32 field in the image prefix
+const CRC_FIELD: usize = 16;
+const PREFIX_LEN: usize = 20;
+
+fuzz_target!(|data: &[u8]| {
+    // a random crc32 would never match, patch in the right one so the
+    // fuzzer gets past the checksum and into the tables
+    let mut data = data.to_vec();
+    if data.len() >= PREFIX_LEN {
+        let l_image = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        let end = l_image.clamp(PREFIX_LEN, data.len());
+        let crc = crc::crc32(&data[PREFIX_LEN..end]);
+        data[CRC_FIELD..PREFIX_LEN].copy_from_slice(&crc.to_le_bytes());
+    }
+    let Ok(mut image) = image::Image::parse(&data) else {
+        return;
+    };
+    // encrypted .text can't be decrypted without the key, compressed .text is decompressed
+    // like the loader does, within reason for the fuzzer's memory
+    if image.encryption().is_some() {
+        return;
+    }
+    let mut text = Vec::new();
+    if image.is_compressed() {
+        if image.header.l_text > 1 << 20 {
+            return;
+        }
+        text.resize(image.header.l_text, 0);
+        if lz4::decompress(image.text, &mut text).is_err() {
+            return;
+        }
+        image = match image.unpack(&text) {
+            Ok(image) => image,
+            Err(_) => return,
+        };
+    }
+    let symbols = image.symbols();
+    for reloc in image.relocs() {
+        let _ = &symbols[reloc.symbol];
+        assert!(reloc.offset + 4 <= image.text.len());
+        assert!(reloc.got_index + 4 <= image.header.l_got);
+    }
+    for idx in image.funcs() {
+        let _ = &symbols[idx];
+    }
+    // resolve patches the movw/movt pair of every trampoline in .text
+    for trampoline in image.trampolines() {
+        let _ = &symbols[trampoline.symbol as usize];
+        assert!(trampoline.offset as usize + image_format::TRAMPOLINE_LEN <= image.header.l_text);
+        let at = trampoline.offset as usize + image_format::TRAMPOLINE_STATIC_BASE;
+        assert_eq!(image.text[at..at + 8], image_format::TRAMPOLINE_UNPATCHED);
+    }
+    // constructors and destructors are called at .text + offset
+    for offset in image.constructors().chain(image.destructors()) {
+        assert!(offset < image.header.l_text);
+    }
+    for reloc in image.data_relocs() {
+        assert!(reloc.offset as usize + 4 <= image.header.l_data);
+        if let image::RelocTarget::Symbol(idx) = reloc.target {
+            let _ = &symbols[idx as usize];
+        }
+    }
+    let _ = image.metadata("name");
+    // the hash table only ever leads to exported symbols
+    let hash = image.symbol_hash();
+    for symbol in &symbols {
+        let s_hash = symbol.s_hash;
+        if let Some(found) = hash.lookup(s_hash, |i| symbols[i].s_hash == s_hash) {
+            assert_eq!(symbols[found].kind, image::SymbolKind::Exported);
+        }
+    }
+});
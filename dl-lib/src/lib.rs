@@ -19,7 +19,11 @@ use cortex_m_rt::entry;
 use cortex_m_semihosting::dbg;
 
 mod utils;
-use utils::{module, module::Module};
+pub use utils::embed::EmbeddedModule;
+// what `include_module!` expands to, only reachable through `$crate`
+#[doc(hidden)]
+pub use image_format::check_embedded as __check_embedded;
+use utils::module::{self, LoadOptions, SignaturePolicy};
 // this is the allocator the application will use
 #[global_allocator]
 static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
@@ -46,14 +50,8 @@ fn init_heap() {
     unsafe { ALLOCATOR.init(cortex_m_rt::heap_start() as usize, heap_size) }
 }
 
-extern "C" {
-    static _binary_module_call_bin_start: u8;
-    static _binary_module_call_bin_end: u8;
-    static _binary_module_call_bin_size: u8;
-    static _binary_module_def_bin_start: u8;
-    static _binary_module_def_bin_end: u8;
-    static _binary_module_def_bin_size: u8;
-}
+static MODULE_DEF: EmbeddedModule = include_module!("module_def", "../module_def.bin");
+static MODULE_CALL: EmbeddedModule = include_module!("module_call", "../module_call.bin");
 
 fn call_func_arg(func: fn(u32) -> u32, arg: u32) -> u32 {
     func(arg)
@@ -64,17 +62,26 @@ fn main() -> ! {
     init_heap();
     // alloc_all
     // resolve_all
-    let p_start_def = unsafe { &_binary_module_def_bin_start as *const u8 };
-    let p_start_call = unsafe { &_binary_module_call_bin_start as *const u8 };
-    let mut module_def = Module::allocate(p_start_def);
-    let mut module_call = Module::allocate(p_start_call);
-    module_def.resolve(p_start_def, None);
-    module_call.resolve(p_start_call, Some(vec![module_def.clone()]));
+    // the example modules aren't signed, load them anyway
+    let options = LoadOptions {
+        signature_policy: SignaturePolicy::WarnOnly,
+        ..LoadOptions::default()
+    };
+    let module_def = MODULE_DEF
+        .load_with(&[], &options)
+        .expect("module_def not loaded");
+    let module_call = MODULE_CALL
+        .load_with(&[&module_def], &options)
+        .expect("module_call not loaded");
+    // whether to trust modules that failed the signature check is up to the firmware
+    dbg!(&module_call.signature);
     let entry = module_call.entry_by_name("test");
     let f = unsafe { mem::transmute::<usize, fn(u32) -> u32>(entry) };
     dbg!(call_func_arg(f, 1));
     let x = module_def.val_by_name("GLOBAL_X", |x| u8::from_le_bytes(x.try_into().unwrap()));
     dbg!(x);
+    // runs the destructors of module_call and frees it, module_def stays loaded
+    module_call.unload();
     loop {}
 }
 
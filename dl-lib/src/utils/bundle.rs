@@ -0,0 +1,47 @@
+//! Module bundles: several images and an index over them in one blob, written by
+//! build_script's `bundle` binary, see `image_format::Bundle` for the layout.
+//!
+//! Opening a bundle only checks its index, the image of a module is checked against
+//! the crc32 in the index when it is taken out of the bundle.
+use image_format::Bundle;
+pub use image_format::BundleModule;
+
+use super::image::LoadError;
+use super::module::{LoadOptions, Module};
+
+/// A bundle whose index has been checked
+#[derive(Debug, Clone, Copy)]
+pub struct ModuleBundle<'a> {
+    bundle: Bundle<'a>,
+}
+
+impl<'a> ModuleBundle<'a> {
+    /// bundle is the whole bundle as embedded in flash
+    pub fn new(bundle: &'a [u8]) -> Result<ModuleBundle<'a>, LoadError> {
+        Ok(ModuleBundle {
+            bundle: Bundle::decode(bundle)?,
+        })
+    }
+    /// every module with its name and version, in index order
+    pub fn modules(&self) -> impl Iterator<Item = BundleModule<'a>> + '_ {
+        self.bundle.iter()
+    }
+    /// the image of module `name`, refused if it doesn't match the crc32 in the index
+    pub fn image(&self, name: &str) -> Result<&'a [u8], LoadError> {
+        let module = self.bundle.find(name).ok_or(LoadError::ModuleNotFound)?;
+        Ok(module.check()?)
+    }
+    /// allocate module `name` and resolve it against `dependencies`, see `Module::load`
+    pub fn load(&self, name: &str, dependencies: &[&Module]) -> Result<Module, LoadError> {
+        self.load_with(name, dependencies, &LoadOptions::default())
+    }
+    /// load with the image checked and unpacked according to `options`, see `Module::allocate_with`
+    pub fn load_with(
+        &self,
+        name: &str,
+        dependencies: &[&Module],
+        options: &LoadOptions,
+    ) -> Result<Module, LoadError> {
+        Module::load(self.image(name)?, dependencies, options)
+    }
+}
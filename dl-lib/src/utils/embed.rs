@@ -0,0 +1,60 @@
+//! Module images compiled into the firmware with `include_module!`
+use super::image::LoadError;
+use super::module::{LoadOptions, Module};
+
+/// An image embedded by `include_module!`, together with the name of its module
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedModule {
+    name: &'static str,
+    image: &'static [u8],
+}
+
+impl EmbeddedModule {
+    /// `image` must be the image of module `name`, `include_module!` checks it at compile time
+    pub const fn new(name: &'static str, image: &'static [u8]) -> EmbeddedModule {
+        EmbeddedModule { name, image }
+    }
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+    pub const fn image(&self) -> &'static [u8] {
+        self.image
+    }
+    /// allocate the module and resolve it against `dependencies`, see `Module::load`
+    pub fn load(&self, dependencies: &[&Module]) -> Result<Module, LoadError> {
+        Module::load(self.image, dependencies, &LoadOptions::default())
+    }
+    /// load with the image checked and unpacked according to `options`
+    pub fn load_with(
+        &self,
+        dependencies: &[&Module],
+        options: &LoadOptions,
+    ) -> Result<Module, LoadError> {
+        Module::load(self.image, dependencies, options)
+    }
+}
+
+/// Embed the image of module `name` from `path` (relative to the current file, like
+/// `include_bytes!`) 4-byte aligned in flash, as an `EmbeddedModule`:
+///
+/// ```ignore
+/// static MODULE_DEF: EmbeddedModule = include_module!("module_def", "../module_def.bin");
+/// ```
+///
+/// Magic, format version, arch tag, length and module name of the image are checked at
+/// compile time, see `image_format::check_embedded`. Its crc32 and records are checked
+/// when it is loaded.
+#[macro_export]
+macro_rules! include_module {
+    ($name:literal, $path:literal) => {{
+        #[repr(C, align(4))]
+        struct Aligned<T: ?Sized>(T);
+        const BYTES: &[u8] = include_bytes!($path);
+        const _: () = match $crate::__check_embedded(BYTES, $name) {
+            Ok(()) => (),
+            Err(err) => panic!("{}", err),
+        };
+        static IMAGE: Aligned<[u8; BYTES.len()]> = Aligned(*include_bytes!($path));
+        $crate::EmbeddedModule::new($name, &IMAGE.0)
+    }};
+}
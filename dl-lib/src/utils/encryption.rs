@@ -0,0 +1,42 @@
+//! AES-256-GCM decryption of the .text and .data of encrypted images
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, Tag};
+use core::fmt;
+
+use super::image::{Encryption, LoadError};
+
+/// Source of the keys encrypted images are decrypted with, implemented by the firmware,
+/// e.g. on top of a secure element or read-protected flash
+pub trait KeyProvider {
+    /// AES-256 key known to build_script as `key_id`, None if the firmware doesn't have it
+    fn key(&self, key_id: u32) -> Option<[u8; 32]>;
+}
+
+impl fmt::Debug for dyn KeyProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("KeyProvider")
+    }
+}
+
+/// Decrypt `payload`, a RAM copy of the image's .text and .data, in place
+///
+/// Nothing is decrypted unless the tag authenticates payload and `aad`, the layout record.
+pub fn decrypt(
+    encryption: &Encryption,
+    aad: &[u8],
+    key_provider: Option<&dyn KeyProvider>,
+    payload: &mut [u8],
+) -> Result<(), LoadError> {
+    let key_id = encryption.key_id;
+    let key = key_provider
+        .and_then(|provider| provider.key(key_id))
+        .ok_or(LoadError::MissingKey { key_id })?;
+    Aes256Gcm::new(&key.into())
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&encryption.nonce),
+            aad,
+            payload,
+            Tag::from_slice(&encryption.tag),
+        )
+        .map_err(|_| LoadError::DecryptionFailed)
+}
@@ -0,0 +1,657 @@
+//! Bounds-checked view of a module image as produced by build_script.
+//!
+//! Everything read from the image (counts, name offsets, relocation offsets and the
+//! GOT indices they point at) is checked before use, so the loader never indexes
+//! outside the image or the GOT it allocates. Records are decoded by the `image-format`
+//! crate shared with build_script, this file only adds what the loader needs on top and
+//! depends on nothing but `core`, `alloc` and that crate, so it also builds on the host,
+//! see `fuzz/`.
+extern crate alloc;
+use alloc::vec::Vec;
+
+pub use image_format::{
+    DataReloc, Encryption, RelocTarget, SymbolEntry, SymbolHash, SymbolKind, Trampoline,
+};
+use image_format::{
+    Entry, FormatError, GotReloc, Layout, Metadata, Prefix, Records, Signature, Symbols, Table,
+    IMAGE_FLAG_COMPRESSED, IMAGE_FLAG_HASHED_NAMES, RECORD_DATA, RECORD_DATA_RELOCS, RECORD_DEBUG,
+    RECORD_ENCRYPTION, RECORD_EXPORTS, RECORD_FINI_ARRAY, RECORD_INIT_ARRAY, RECORD_LAYOUT,
+    RECORD_METADATA, RECORD_RELOCS, RECORD_REQUIRED, RECORD_SIGNATURE, RECORD_SYMBOLS,
+    RECORD_SYMBOL_HASH, RECORD_TEXT, RECORD_TRAMPOLINES, TRAMPOLINE_LEN, TRAMPOLINE_STATIC_BASE,
+    TRAMPOLINE_UNPATCHED,
+};
+
+/// The image prefix, the layout record and the counts implied by the lengths of the others
+#[derive(Debug, Clone)]
+pub struct ModuleHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub arch: u32,
+    pub l_image: u32,
+    pub crc32: u32,
+    pub n_funcs: usize,
+    /// functions among them entered through a trampoline in .text rather than a PLT entry
+    pub n_trampolines: usize,
+    pub n_reloc: usize,
+    pub l_symt: usize,
+    pub l_text: usize,
+    pub l_data: usize,
+    pub l_bss: usize,
+    pub n_symbol: usize,
+    pub n_data_reloc: usize,
+    /// size in bytes of the GOT, .data starts right after it, R9 points at its start
+    pub l_got: usize,
+    /// size in bytes of the hash table over the exported symbols
+    pub l_hash: usize,
+    pub flags: u32,
+    /// size in bytes of .text/.data in the image, l_text/l_data unless compressed
+    pub l_text_stored: usize,
+    pub l_data_stored: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    /// index1 is an offset into .text rather than .data
+    pub in_text: bool,
+    pub index1: usize,
+    pub index2: usize,
+    /// `gnu_hash` of the name, symbols are identified by it, 0 for local symbols
+    pub s_hash: u32,
+    /// where the name starts in `Image::names`
+    pub name_offset: usize,
+}
+
+/// A GOT relocation: the word at `offset` in .text holds the GOT slot (in bytes)
+/// that must receive the address of symbol `symbol`
+#[derive(Debug, Clone, Copy)]
+pub struct Reloc {
+    pub offset: usize,
+    pub symbol: usize,
+    pub got_index: usize,
+}
+
+/// Reasons for refusing to load an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// fewer bytes than the prefix, or than the image length recorded in it
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    ArchMismatch(u32),
+    /// flags this loader doesn't know about
+    UnsupportedFlags(u32),
+    /// a record this loader doesn't know about but must not skip
+    UnsupportedRecord(u32),
+    /// a record that appears more than once
+    DuplicateRecord(u32),
+    /// a record every image must have
+    MissingRecord(u32),
+    /// crc32 of the payload differs from the one recorded by build_script
+    Corrupted {
+        expected: u32,
+        actual: u32,
+    },
+    /// records that run past the image, have the wrong size or don't add up
+    BadLayout,
+    /// a symbol entry with an unknown type
+    BadSymbolType {
+        symbol: usize,
+    },
+    /// a defined symbol whose address lies outside its section
+    SymbolOutOfRange {
+        symbol: usize,
+        address: usize,
+    },
+    /// a symbol name that starts outside the name table or has no terminating NUL
+    BadSymbolName {
+        symbol: usize,
+        offset: usize,
+    },
+    /// an encrypted image whose key the key provider doesn't have
+    MissingKey {
+        key_id: u32,
+    },
+    /// encrypted .text/.data that fail authentication with the key
+    DecryptionFailed,
+    /// an image without signature record where the policy requires one
+    Unsigned,
+    /// an image signed with a key that isn't among the trusted keys
+    UntrustedKey,
+    /// a signature that doesn't match the image
+    BadSignature,
+    /// a relocation or function index naming a symbol past the end of the symbol table
+    BadSymbolIndex {
+        index: usize,
+        n_symbol: usize,
+    },
+    /// a relocation whose word doesn't lie within .text
+    RelocOutOfText {
+        offset: usize,
+        l_text: usize,
+    },
+    /// a relocation whose GOT slot lies outside the GOT allocated for the module
+    GotIndexOutOfRange {
+        offset: usize,
+        got_index: usize,
+    },
+    /// a data relocation whose word doesn't lie within .data
+    DataRelocOutOfData {
+        offset: usize,
+        l_data: usize,
+    },
+    /// a data relocation with an unknown target kind
+    BadDataRelocTarget {
+        index: usize,
+    },
+    /// compressed .text or .data that doesn't decompress to the size in the layout record
+    BadCompression,
+    /// the symbol hash table is malformed or names a symbol past the end of the symbol table
+    BadHashTable,
+    /// an external symbol none of the dependencies defines
+    UnresolvedSymbol {
+        symbol: usize,
+    },
+    /// `Module::resolve` was handed a different image than the module was allocated from
+    ImageMismatch,
+    /// a trampoline outside .text, for a symbol that isn't an exported function or one that
+    /// already has a trampoline, or without the movw/movt pair the loader patches
+    BadTrampoline {
+        index: usize,
+    },
+    /// a constructor or destructor that isn't a Thumb function within .text
+    FunctionOutOfText {
+        offset: usize,
+        l_text: usize,
+    },
+    /// a bundle index entry whose name, version or image lies outside the bundle
+    BadBundleEntry {
+        index: usize,
+    },
+    /// a bundle without a module of the requested name
+    ModuleNotFound,
+}
+
+impl From<FormatError> for LoadError {
+    fn from(err: FormatError) -> LoadError {
+        match err {
+            FormatError::Truncated { expected, actual } => {
+                LoadError::Truncated { expected, actual }
+            }
+            FormatError::BadMagic(magic) => LoadError::BadMagic(magic),
+            FormatError::UnsupportedVersion(version) => LoadError::UnsupportedVersion(version),
+            FormatError::ArchMismatch(arch) => LoadError::ArchMismatch(arch),
+            FormatError::Corrupted { expected, actual } => {
+                LoadError::Corrupted { expected, actual }
+            }
+            FormatError::BadLayout => LoadError::BadLayout,
+            FormatError::BadIndexEntry(index) => LoadError::BadBundleEntry { index },
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Payloads of the records this loader knows, by tag
+#[derive(Default)]
+struct KnownRecords<'a> {
+    layout: Option<&'a [u8]>,
+    text: Option<&'a [u8]>,
+    data: Option<&'a [u8]>,
+    symbols: Option<&'a [u8]>,
+    relocs: Option<&'a [u8]>,
+    exports: Option<&'a [u8]>,
+    data_relocs: Option<&'a [u8]>,
+    hash: Option<&'a [u8]>,
+    encryption: Option<&'a [u8]>,
+    metadata: Option<&'a [u8]>,
+    signature: Option<&'a [u8]>,
+    trampolines: Option<&'a [u8]>,
+    init_array: Option<&'a [u8]>,
+    fini_array: Option<&'a [u8]>,
+}
+
+impl<'a> KnownRecords<'a> {
+    /// sort the records of `body` by tag, skipping unknown optional ones,
+    /// also returns where the signature record starts, if there is one
+    fn split(body: &'a [u8]) -> Result<(KnownRecords<'a>, Option<usize>), LoadError> {
+        let mut records = KnownRecords::default();
+        let mut signature_at = None;
+        for record in Records::new(body) {
+            let record = record?;
+            // the signature covers everything before it, nothing may follow
+            if signature_at.is_some() {
+                return Err(LoadError::BadLayout);
+            }
+            let slot = match record.tag {
+                RECORD_LAYOUT => &mut records.layout,
+                RECORD_TEXT => &mut records.text,
+                RECORD_DATA => &mut records.data,
+                RECORD_SYMBOLS => &mut records.symbols,
+                RECORD_RELOCS => &mut records.relocs,
+                RECORD_EXPORTS => &mut records.exports,
+                RECORD_DATA_RELOCS => &mut records.data_relocs,
+                RECORD_SYMBOL_HASH => &mut records.hash,
+                RECORD_ENCRYPTION => &mut records.encryption,
+                RECORD_METADATA => &mut records.metadata,
+                RECORD_TRAMPOLINES => &mut records.trampolines,
+                RECORD_INIT_ARRAY => &mut records.init_array,
+                RECORD_FINI_ARRAY => &mut records.fini_array,
+                RECORD_DEBUG => continue,
+                RECORD_SIGNATURE => {
+                    signature_at = Some(record.offset);
+                    &mut records.signature
+                }
+                tag if tag & RECORD_REQUIRED != 0 => return Err(LoadError::UnsupportedRecord(tag)),
+                _ => continue,
+            };
+            if slot.replace(record.payload).is_some() {
+                return Err(LoadError::DuplicateRecord(record.tag));
+            }
+        }
+        Ok((records, signature_at))
+    }
+}
+
+/// `record` as a table of `T`, empty if the image doesn't have it
+fn table<'a, T: Entry + 'a>(record: Option<&'a [u8]>) -> Result<Table<'a, T>, LoadError> {
+    Table::new(record.unwrap_or_default()).ok_or(LoadError::BadLayout)
+}
+
+/// a single fixed-size entry, None if the image doesn't have the record
+fn entry<T: Entry>(record: Option<&[u8]>) -> Result<Option<T>, LoadError> {
+    record
+        .map(|record| T::decode(record).ok_or(LoadError::BadLayout))
+        .transpose()
+}
+
+/// A validated image, all accessors are safe to call once `parse` succeeded
+#[derive(Debug)]
+pub struct Image<'a> {
+    pub header: ModuleHeader,
+    /// .text, or the LZ4 block holding it if the image is compressed, encrypted if the image
+    /// is, until unpacked
+    pub text: &'a [u8],
+    /// .data, or the LZ4 block holding it if the image is compressed, encrypted if the image is
+    pub data: &'a [u8],
+    /// the layout record, authenticated along with .text and .data of an encrypted image
+    layout: &'a [u8],
+    encryption: Option<Encryption>,
+    /// .text is still compressed or encrypted, the GOT indices in it can't be read yet
+    packed: bool,
+    /// the records before the signature record, what a signature covers
+    signed: &'a [u8],
+    signature: Option<Signature>,
+    metadata: Metadata<'a>,
+    symbols: Symbols<'a>,
+    relocs: Table<'a, GotReloc>,
+    funcs: Table<'a, u32>,
+    data_relocs: Table<'a, DataReloc>,
+    hash: SymbolHash<'a>,
+    trampolines: Table<'a, Trampoline>,
+    init_array: Table<'a, u32>,
+    fini_array: Table<'a, u32>,
+}
+
+impl<'a> Image<'a> {
+    /// Check the prefix and every record of the image
+    pub fn parse(image: &'a [u8]) -> Result<Image<'a>, LoadError> {
+        let (prefix, body) = Prefix::decode(image)?;
+        let (records, signature_at) = KnownRecords::split(body)?;
+        let layout_record = records
+            .layout
+            .ok_or(LoadError::MissingRecord(RECORD_LAYOUT))?;
+        let layout = Layout::decode(layout_record).ok_or(LoadError::BadLayout)?;
+        let flags = layout.flags;
+        if flags & !(IMAGE_FLAG_HASHED_NAMES | IMAGE_FLAG_COMPRESSED) != 0 {
+            return Err(LoadError::UnsupportedFlags(flags));
+        }
+        let text = records.text.unwrap_or_default();
+        let data = records.data.unwrap_or_default();
+        let symbols = match records.symbols {
+            Some(symbols) => Symbols::decode(symbols).ok_or(LoadError::BadLayout)?,
+            None => Symbols::default(),
+        };
+        let relocs = table(records.relocs)?;
+        let funcs = table(records.exports)?;
+        let data_relocs = table(records.data_relocs)?;
+        let trampolines = table(records.trampolines)?;
+        let init_array = table(records.init_array)?;
+        let fini_array = table(records.fini_array)?;
+        let hash =
+            SymbolHash::decode(records.hash.unwrap_or_default()).ok_or(LoadError::BadHashTable)?;
+        let header = ModuleHeader {
+            magic: prefix.magic,
+            version: prefix.version,
+            arch: prefix.arch,
+            l_image: prefix.l_image,
+            crc32: prefix.crc32,
+            n_funcs: funcs.len(),
+            n_trampolines: trampolines.len(),
+            n_reloc: relocs.len(),
+            l_symt: symbols.entries.as_bytes().len() + symbols.names.len(),
+            l_text: layout.text as usize,
+            l_data: layout.data as usize,
+            l_bss: layout.bss as usize,
+            n_symbol: symbols.entries.len(),
+            n_data_reloc: data_relocs.len(),
+            l_got: layout.got as usize,
+            l_hash: hash.as_bytes().len(),
+            flags,
+            l_text_stored: text.len(),
+            l_data_stored: data.len(),
+        };
+        let compressed = flags & IMAGE_FLAG_COMPRESSED != 0;
+        if !compressed && (text.len() != header.l_text || data.len() != header.l_data) {
+            return Err(LoadError::BadLayout);
+        }
+        let encryption = entry::<Encryption>(records.encryption)?;
+        // .bss isn't stored, but GOT, .data and .bss are allocated as one block,
+        // .data must stay word aligned behind the GOT
+        header
+            .l_got
+            .checked_add(header.l_data)
+            .and_then(|len| len.checked_add(header.l_bss))
+            .filter(|_| header.l_got.is_multiple_of(4))
+            .ok_or(LoadError::BadLayout)?;
+        let parsed = Image {
+            header,
+            text,
+            data,
+            layout: layout_record,
+            packed: compressed || encryption.is_some(),
+            encryption,
+            signed: &body[..signature_at.unwrap_or(body.len())],
+            signature: entry(records.signature)?,
+            metadata: Metadata::new(records.metadata.unwrap_or_default()),
+            symbols,
+            relocs,
+            funcs,
+            data_relocs,
+            hash,
+            trampolines,
+            init_array,
+            fini_array,
+        };
+        parsed.check_symbols()?;
+        parsed.check_relocs()?;
+        parsed.check_funcs()?;
+        parsed.check_data_relocs()?;
+        parsed.check_hash()?;
+        parsed.check_trampolines()?;
+        parsed.check_function_arrays()?;
+        Ok(parsed)
+    }
+
+    /// entry `symbol` of the symbol table, checked by `check_symbols`
+    fn symbol_entry(&self, symbol: usize) -> SymbolEntry {
+        self.symbols.entries.get(symbol).unwrap()
+    }
+
+    /// hash of the name of `entry`, None for a name outside the names or without a NUL
+    fn name_hash(&self, entry: &SymbolEntry) -> Option<u32> {
+        let hashed = self.header.flags & IMAGE_FLAG_HASHED_NAMES != 0;
+        self.symbols.name_hash(entry.name_offset, hashed)
+    }
+
+    fn check_symbols(&self) -> Result<(), LoadError> {
+        let l_static = self.header.l_data.saturating_add(self.header.l_bss);
+        for (symbol, entry) in self.symbols.entries.iter().enumerate() {
+            let entry = entry.ok_or(LoadError::BadSymbolType { symbol })?;
+            let limit = if entry.in_text {
+                self.header.l_text
+            } else {
+                l_static
+            };
+            let address = entry.address as usize;
+            if entry.kind != SymbolKind::External && address >= limit {
+                return Err(LoadError::SymbolOutOfRange { symbol, address });
+            }
+            // local varable needs no name
+            if entry.kind != SymbolKind::Local && self.name_hash(&entry).is_none() {
+                return Err(LoadError::BadSymbolName {
+                    symbol,
+                    offset: entry.name_offset as usize,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), LoadError> {
+        if index >= self.header.n_symbol {
+            return Err(LoadError::BadSymbolIndex {
+                index,
+                n_symbol: self.header.n_symbol,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_relocs(&self) -> Result<(), LoadError> {
+        let l_text = self.header.l_text;
+        for reloc in self.relocs.iter().flatten() {
+            let offset = reloc.offset as usize;
+            self.check_index(reloc.symbol as usize)?;
+            if offset.checked_add(4).is_none_or(|end| end > l_text) {
+                return Err(LoadError::RelocOutOfText { offset, l_text });
+            }
+            // checked by `unpack`
+            if self.packed {
+                continue;
+            }
+            let got_index = read_u32(self.text, offset) as usize;
+            if got_index
+                .checked_add(4)
+                .is_none_or(|end| end > self.header.l_got)
+            {
+                return Err(LoadError::GotIndexOutOfRange { offset, got_index });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_funcs(&self) -> Result<(), LoadError> {
+        self.funcs().try_for_each(|idx| self.check_index(idx))
+    }
+
+    fn check_data_relocs(&self) -> Result<(), LoadError> {
+        let l_data = self.header.l_data;
+        for (index, reloc) in self.data_relocs.iter().enumerate() {
+            let reloc = reloc.ok_or(LoadError::BadDataRelocTarget { index })?;
+            let offset = reloc.offset as usize;
+            if offset.checked_add(4).is_none_or(|end| end > l_data) {
+                return Err(LoadError::DataRelocOutOfData { offset, l_data });
+            }
+            if let RelocTarget::Symbol(symbol) = reloc.target {
+                self.check_index(symbol as usize)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// every entry of the hash table must lead to an exported symbol with that hash
+    fn check_hash(&self) -> Result<(), LoadError> {
+        for (entry_hash, symbol) in self.hash.entries() {
+            if symbol >= self.header.n_symbol {
+                return Err(LoadError::BadHashTable);
+            }
+            let entry = self.symbol_entry(symbol);
+            if entry.kind != SymbolKind::Exported
+                || self.name_hash(&entry).unwrap() | 1 != entry_hash | 1
+            {
+                return Err(LoadError::BadHashTable);
+            }
+        }
+        Ok(())
+    }
+
+    /// every trampoline must lie within .text, stand for a different exported function and
+    /// hold the movw/movt pair the loader patches, checked by `unpack` if .text is packed
+    fn check_trampolines(&self) -> Result<(), LoadError> {
+        for (index, trampoline) in self.trampolines.iter().enumerate() {
+            let valid = trampoline.is_some_and(|trampoline| {
+                let offset = trampoline.offset as usize;
+                let symbol = trampoline.symbol as usize;
+                offset.is_multiple_of(2)
+                    && offset
+                        .checked_add(TRAMPOLINE_LEN)
+                        .is_some_and(|end| end <= self.header.l_text)
+                    && self.funcs().any(|idx| idx == symbol)
+                    && self
+                        .trampolines()
+                        .take(index)
+                        .all(|other| other.symbol != trampoline.symbol)
+                    && (self.packed || {
+                        let at = offset + TRAMPOLINE_STATIC_BASE;
+                        self.text[at..at + TRAMPOLINE_UNPATCHED.len()] == TRAMPOLINE_UNPATCHED
+                    })
+            });
+            if !valid {
+                return Err(LoadError::BadTrampoline { index });
+            }
+        }
+        Ok(())
+    }
+
+    /// constructors and destructors must be Thumb functions within .text
+    fn check_function_arrays(&self) -> Result<(), LoadError> {
+        let l_text = self.header.l_text;
+        for offset in self.constructors().chain(self.destructors()) {
+            if offset & 1 == 0 || offset >= l_text {
+                return Err(LoadError::FunctionOutOfText { offset, l_text });
+            }
+        }
+        Ok(())
+    }
+
+    /// the records between the prefix and the signature record, what the signature covers
+    pub fn signed_bytes(&self) -> &'a [u8] {
+        self.signed
+    }
+
+    /// public key and signature of the signature record, if any
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    /// value of `key` in the metadata record, if the image has one
+    pub fn metadata(&self, key: &str) -> Option<&'a [u8]> {
+        self.metadata.get(key)
+    }
+
+    /// true if .text and .data are stored compressed
+    pub fn is_compressed(&self) -> bool {
+        self.header.flags & IMAGE_FLAG_COMPRESSED != 0
+    }
+
+    /// key id, nonce and tag if .text and .data are stored encrypted,
+    /// and the layout record they are authenticated along with
+    pub fn encryption(&self) -> Option<(&Encryption, &'a [u8])> {
+        self.encryption
+            .as_ref()
+            .map(|encryption| (encryption, self.layout))
+    }
+
+    /// true if .text can't run in place, because it is stored compressed or encrypted
+    pub fn is_packed(&self) -> bool {
+        self.is_compressed() || self.encryption.is_some()
+    }
+
+    /// Switch a compressed or encrypted image over to its plain .text in RAM,
+    /// and check the GOT indices the relocations and the trampolines point at in it
+    pub fn unpack(mut self, text: &'a [u8]) -> Result<Image<'a>, LoadError> {
+        if text.len() != self.header.l_text {
+            return Err(LoadError::BadCompression);
+        }
+        self.text = text;
+        self.packed = false;
+        self.check_relocs()?;
+        self.check_trampolines()?;
+        Ok(self)
+    }
+
+    /// the NUL-terminated names of the symbols, None if the image only stores their hashes
+    pub fn names(&self) -> Option<&'a [u8]> {
+        (self.header.flags & IMAGE_FLAG_HASHED_NAMES == 0).then_some(self.symbols.names)
+    }
+
+    /// hash table over the exported symbols
+    pub fn symbol_hash(&self) -> SymbolHash<'a> {
+        self.hash
+    }
+
+    /// decode the symbol table, exported and external symbols keep the hash of their name
+    pub fn symbols(&self) -> Vec<Symbol> {
+        (0..self.header.n_symbol)
+            .map(|i| {
+                let entry = self.symbol_entry(i);
+                let s_hash = if entry.kind != SymbolKind::Local {
+                    self.name_hash(&entry).unwrap()
+                } else {
+                    0
+                };
+                Symbol {
+                    kind: entry.kind,
+                    in_text: entry.in_text,
+                    index1: entry.address as usize,
+                    index2: 0,
+                    s_hash,
+                    name_offset: entry.name_offset as usize,
+                }
+            })
+            .collect()
+    }
+
+    /// GOT relocations, in image order, a packed image must be unpacked first
+    pub fn relocs(&self) -> impl Iterator<Item = Reloc> + '_ {
+        assert!(
+            !self.packed,
+            "relocations of a packed image read before unpack"
+        );
+        self.relocs.iter().flatten().map(|reloc| {
+            let offset = reloc.offset as usize;
+            Reloc {
+                offset,
+                symbol: reloc.symbol as usize,
+                got_index: read_u32(self.text, offset) as usize,
+            }
+        })
+    }
+
+    /// symbol table index of every global function, in image order
+    pub fn funcs(&self) -> impl Iterator<Item = usize> + '_ {
+        self.funcs.iter().flatten().map(|idx| idx as usize)
+    }
+
+    /// exported functions entered through a trampoline, in image order
+    pub fn trampolines(&self) -> impl Iterator<Item = Trampoline> + '_ {
+        self.trampolines.iter().flatten()
+    }
+
+    /// .text offsets of the constructors, with the thumb bit, in the order they run
+    pub fn constructors(&self) -> impl Iterator<Item = usize> + '_ {
+        self.init_array
+            .iter()
+            .flatten()
+            .map(|offset| offset as usize)
+    }
+
+    /// .text offsets of the destructors, with the thumb bit, in the order they run
+    pub fn destructors(&self) -> impl Iterator<Item = usize> + '_ {
+        self.fini_array
+            .iter()
+            .flatten()
+            .map(|offset| offset as usize)
+    }
+
+    /// pointers in .data, in image order
+    pub fn data_relocs(&self) -> impl Iterator<Item = DataReloc> + '_ {
+        self.data_relocs.iter().flatten()
+    }
+}
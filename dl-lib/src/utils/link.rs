@@ -0,0 +1,76 @@
+//! Filling the GOT and relocating the pointers in .data of a module being resolved.
+//!
+//! Like `image.rs` this only depends on `core` and the image, so it builds on the host
+//! and the loader's handling of missing definitions can be tested there.
+use super::image::{Image, LoadError, RelocTarget, Symbol, SymbolKind};
+
+/// Where .text and .data of a module are at runtime
+#[derive(Debug, Clone, Copy)]
+pub struct Bases {
+    pub text: usize,
+    pub data: usize,
+}
+
+impl Bases {
+    /// runtime address of a symbol the module defines
+    fn address_of(&self, symbol: &Symbol) -> usize {
+        symbol.index1 + if symbol.in_text { self.text } else { self.data }
+    }
+}
+
+fn write_word(bytes: &mut [u8], value: usize) {
+    bytes.copy_from_slice(&(value as u32).to_le_bytes());
+}
+
+/// Write the address of every symbol the GOT relocations of `image` refer to into its GOT
+/// slot, external symbols are looked up with `lookup`
+///
+/// `symbols` is the symbol table of `image` and `got` the GOT allocated for it,
+/// `image` must have been unpacked.
+pub fn fill_got(
+    image: &Image,
+    symbols: &[Symbol],
+    bases: Bases,
+    got: &mut [u8],
+    lookup: impl Fn(&Symbol) -> Option<usize>,
+) -> Result<(), LoadError> {
+    for reloc in image.relocs() {
+        let sym = &symbols[reloc.symbol];
+        let entry = match sym.kind {
+            SymbolKind::Exported | SymbolKind::Local => bases.address_of(sym),
+            SymbolKind::External => lookup(sym).ok_or(LoadError::UnresolvedSymbol {
+                symbol: reloc.symbol,
+            })?,
+        };
+        write_word(&mut got[reloc.got_index..reloc.got_index + 4], entry);
+    }
+    Ok(())
+}
+
+/// Add the runtime base to every pointer in `data`, the .data allocated for `image`
+///
+/// The pointers hold an offset into .text/.data or an addend to an external symbol,
+/// which is looked up with `lookup`.
+pub fn relocate_data(
+    image: &Image,
+    symbols: &[Symbol],
+    bases: Bases,
+    data: &mut [u8],
+    lookup: impl Fn(&Symbol) -> Option<usize>,
+) -> Result<(), LoadError> {
+    for reloc in image.data_relocs() {
+        let offset = reloc.offset as usize;
+        let word = &mut data[offset..offset + 4];
+        let base = match reloc.target {
+            RelocTarget::Text => bases.text,
+            RelocTarget::Data => bases.data,
+            RelocTarget::Symbol(idx) => {
+                let symbol = idx as usize;
+                lookup(&symbols[symbol]).ok_or(LoadError::UnresolvedSymbol { symbol })?
+            }
+        };
+        let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        word.copy_from_slice(&value.wrapping_add(base as u32).to_le_bytes());
+    }
+    Ok(())
+}
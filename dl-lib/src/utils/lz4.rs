@@ -0,0 +1,73 @@
+//! Decompression of the LZ4 blocks build_script stores compressed .text/.data in.
+//!
+//! Each block is decompressed in one go, straight into the memory allocated for its
+//! section, matches are copied from what was already written there. Like `image.rs`
+//! this only depends on `core`, so it builds on the host for fuzzing.
+use super::image::LoadError;
+
+const MIN_MATCH: usize = 4;
+
+fn next_byte(input: &[u8], pos: &mut usize) -> Result<u8, LoadError> {
+    let byte = *input.get(*pos).ok_or(LoadError::BadCompression)?;
+    *pos += 1;
+    Ok(byte)
+}
+
+/// nibble of a token plus the 255-runs that follow it if it is 15
+fn length(input: &[u8], pos: &mut usize, nibble: usize) -> Result<usize, LoadError> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = next_byte(input, pos)?;
+            len = len
+                .checked_add(byte as usize)
+                .ok_or(LoadError::BadCompression)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Decompress the block `input` into `out`, which must be exactly the size of the output
+pub fn decompress(input: &[u8], out: &mut [u8]) -> Result<(), LoadError> {
+    let mut pos = 0;
+    let mut written = 0;
+    while pos < input.len() {
+        let token = next_byte(input, &mut pos)? as usize;
+        let n_literals = length(input, &mut pos, token >> 4)?;
+        let literals = pos
+            .checked_add(n_literals)
+            .and_then(|end| input.get(pos..end))
+            .ok_or(LoadError::BadCompression)?;
+        out.get_mut(written..)
+            .and_then(|rest| rest.get_mut(..n_literals))
+            .ok_or(LoadError::BadCompression)?
+            .copy_from_slice(literals);
+        pos += n_literals;
+        written += n_literals;
+        // the last sequence ends after its literals
+        if pos == input.len() {
+            break;
+        }
+        let offset =
+            u16::from_le_bytes([next_byte(input, &mut pos)?, next_byte(input, &mut pos)?]) as usize;
+        if offset == 0 || offset > written {
+            return Err(LoadError::BadCompression);
+        }
+        let end = length(input, &mut pos, token & 0xf)?
+            .checked_add(MIN_MATCH + written)
+            .filter(|end| *end <= out.len())
+            .ok_or(LoadError::BadCompression)?;
+        // a match longer than its offset repeats bytes it has just written
+        for at in written..end {
+            out[at] = out[at - offset];
+        }
+        written = end;
+    }
+    if written != out.len() {
+        return Err(LoadError::BadCompression);
+    }
+    Ok(())
+}
@@ -1,3 +1,10 @@
+pub mod bundle;
+pub mod embed;
+pub mod encryption;
+pub mod image;
 pub mod instr;
+pub mod link;
+pub mod lz4;
 pub mod module;
+pub mod signature;
 pub mod template;
@@ -1,60 +1,18 @@
 extern crate alloc;
-use alloc::{string::String, vec, vec::Vec};
+use alloc::{vec, vec::Vec};
 use core::alloc::{GlobalAlloc, Layout};
-use core::{mem, slice};
+use core::{mem, ptr, slice};
+use cortex_m::asm;
+use image_format::{gnu_hash, TRAMPOLINE_STATIC_BASE};
 
-use super::{instr, template};
+pub use super::encryption::KeyProvider;
+use super::image::{Image, SymbolHash};
+pub use super::image::{LoadError, Symbol};
+use super::link::{self, Bases};
+pub use super::signature::{SignaturePolicy, SignatureStatus};
+use super::{encryption, instr, lz4, signature, template};
 use crate::{Range, ALLOCATOR, LR_RANGE_TO_BASE};
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct ModuleHeader {
-    pub n_funcs: usize,
-    pub n_reloc: usize,
-    pub l_symt: usize,
-    pub l_text: usize,
-    pub l_data: usize,
-    pub l_bss: usize,
-    pub n_symbol: usize,
-}
-
-#[derive(Debug, Clone)]
-pub struct Symbol {
-    pub s_type: u8,
-    pub index1: usize,
-    pub index2: usize,
-    pub s_name: String,
-}
-
-fn parse_symtable(n_symbol: &usize, data: &Vec<u8>) -> Vec<Symbol> {
-    let mut symbols = Vec::new();
-    for i in 0..*n_symbol {
-        let p = i * 8;
-        let x = u32::from_le_bytes(data[p..p + 4].try_into().unwrap());
-        let index = usize::from_le_bytes(data[p + 4..p + 8].try_into().unwrap());
-        let s_type = ((x & (7 << 28)) >> 28) as u8;
-        let n_pos = (x & !(7 << 28)) as usize;
-        // let s_name be the String between q and next 0 in data
-        let mut s_name = String::new();
-        // local varable needs no name
-        if s_type & 3 != 0 {
-            let mut q = 8 * *n_symbol + n_pos;
-            while data[q] != 0 {
-                s_name.push(data[q].into());
-                q += 1;
-            }
-        }
-        symbols.push(Symbol {
-            s_type,
-            index1: index,
-            index2: 0,
-            s_name,
-        });
-    }
-    symbols
-}
-
-const HEADER_LEN: usize = mem::size_of::<ModuleHeader>();
 #[derive(Debug, Clone)]
 pub struct ModulePtr {
     pub got_begin: usize,
@@ -63,26 +21,145 @@ pub struct ModulePtr {
     pub text_begin: usize,
     pub text_end: usize,
 }
-/// Loaded Module
-#[derive(Debug, Clone)]
+/// How `Module::allocate_with` checks and unpacks an image before loading it
+#[derive(Debug, Clone, Copy)]
+pub struct LoadOptions {
+    pub signature_policy: SignaturePolicy,
+    pub trusted_keys: &'static [[u8; 32]],
+    /// keys for encrypted images, these are refused without one
+    pub key_provider: Option<&'static dyn KeyProvider>,
+}
+
+impl Default for LoadOptions {
+    /// only images signed with one of the compiled-in trusted keys are loaded
+    fn default() -> Self {
+        LoadOptions {
+            signature_policy: SignaturePolicy::Require,
+            trusted_keys: signature::TRUSTED_KEYS,
+            key_provider: None,
+        }
+    }
+}
+
+/// Loaded Module, it owns its memory and gives it back in `unload`, so it can't be cloned,
+/// modules resolved against it only borrow it
+#[derive(Debug)]
 pub struct Module {
     pub sym_table: Vec<Symbol>,
     pub ptrs: ModulePtr,
+    /// hash table over the exported symbols, copied from the image
+    pub symbol_hash: Vec<u8>,
+    /// names of the symbols, copied from the image unless it only stores their hashes
+    pub names: Option<Vec<u8>>,
+    /// whether the image was signed by a trusted key, checked once by `allocate`
+    pub signature: SignatureStatus,
+    /// addresses of the destructors, in the order `unload` runs them
+    pub destructors: Vec<usize>,
+    /// blocks from `malloc` the module owns, address and size, given back by `unload`
+    allocations: Vec<(usize, usize)>,
+    /// what `allocate` sized those blocks for, `resolve` refuses images that need more
+    sizes: Sizes,
+    /// address and length of the image `allocate` checked, `resolve` only takes that one
+    image: (usize, usize),
+}
+
+/// Sizes of the parts of a module `allocate` takes memory for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sizes {
+    l_text: usize,
+    l_got: usize,
+    l_data: usize,
+    l_bss: usize,
+    n_symbol: usize,
+    /// functions entered through the plt rather than a trampoline
+    n_plt: usize,
 }
 
-/// given start address and length, extract the region [start, start + length) to a vector
-/// the assign start + length to start
-fn acquire_vec(start: &mut usize, length: usize) -> Vec<u8> {
-    let p_start = *start as *const u8;
-    let slice = unsafe { slice::from_raw_parts(p_start, length) };
-    *start += length;
-    slice.to_vec()
+impl Sizes {
+    fn of(image: &Image) -> Sizes {
+        let header = &image.header;
+        Sizes {
+            l_text: header.l_text,
+            l_got: header.l_got,
+            l_data: header.l_data,
+            l_bss: header.l_bss,
+            n_symbol: header.n_symbol,
+            n_plt: plt_funcs(image).len(),
+        }
+    }
 }
+
+/// symbol table index of every function entered through the plt, in image order,
+/// functions with a trampoline are entered through it
+fn plt_funcs(image: &Image) -> Vec<usize> {
+    image
+        .funcs()
+        .filter(|idx| image.trampolines().all(|t| t.symbol as usize != *idx))
+        .collect()
+}
+
 /// allocate n bytes from the heap and return a pointer to the beginning of the allocated memory
 fn malloc(n: usize, align: usize) -> *mut u8 {
     unsafe { ALLOCATOR.alloc(Layout::from_size_align(n, align).unwrap()) }
 }
 
+/// give back memory from `malloc(n, align)`
+fn free(ptr: *mut u8, n: usize, align: usize) {
+    unsafe { ALLOCATOR.dealloc(ptr, Layout::from_size_align(n, align).unwrap()) }
+}
+
+/// blocks from `malloc` taken while allocating a module, address and size,
+/// given back on drop unless the module takes them over
+struct Allocations(Vec<(usize, usize)>);
+
+impl Allocations {
+    fn alloc(&mut self, n: usize) -> *mut u8 {
+        let ptr = malloc(n, 4);
+        self.0.push((ptr as usize, n));
+        ptr
+    }
+    /// like `alloc`, but an empty block isn't allocated and gets a dangling, aligned pointer
+    fn alloc_or_dangling(&mut self, n: usize) -> *mut u8 {
+        if n == 0 {
+            return ptr::NonNull::<u32>::dangling().as_ptr().cast();
+        }
+        self.alloc(n)
+    }
+    /// give back a block from `alloc` before the module is complete
+    fn free(&mut self, ptr: *mut u8) {
+        if let Some(pos) = self.0.iter().position(|(p, _)| *p == ptr as usize) {
+            let (ptr, len) = self.0.swap_remove(pos);
+            free(ptr as *mut u8, len, 4);
+        }
+    }
+    fn into_inner(mut self) -> Vec<(usize, usize)> {
+        mem::take(&mut self.0)
+    }
+}
+
+impl Drop for Allocations {
+    fn drop(&mut self) {
+        for (ptr, len) in self.0.drain(..) {
+            free(ptr as *mut u8, len, 4);
+        }
+    }
+}
+
+/// call `func`, a constructor or destructor of a module, with R9 at `static_base`,
+/// the caller's R9 is restored afterwards
+unsafe fn call_with_static_base(func: usize, static_base: usize) {
+    core::arch::asm!(
+        // r10 only keeps the stack 8-byte aligned
+        "push {{r9, r10}}",
+        "mov r9, r0",
+        "blx r12",
+        "pop {{r9, r10}}",
+        in("r0") static_base,
+        in("r12") func,
+        clobber_abi("C"),
+    );
+}
+
 /// Generate plt
 /// The plt consist of two parts, manual calls and cross boundary calls
 /// The first part is for calls from the core, which doesn't require the recovery of r9 after function
@@ -115,33 +192,135 @@ fn generate_plt(func_entries: Vec<[u8; 4]>, block_size: usize, got_begin: usize)
 }
 
 impl Module {
-    // search symbol by name
-    fn get_symbol(&self, name: &str) -> Option<&Symbol> {
-        self.sym_table.iter().find(|s| s.s_name == name)
+    // search exported symbol by the hash of its name,
+    // and by the name itself when both it and ours are known
+    fn get_symbol(&self, hash: u32, name: Option<&[u8]>) -> Option<&Symbol> {
+        let idx = SymbolHash::new(&self.symbol_hash).lookup(hash, |idx| {
+            self.sym_table.get(idx).is_some_and(|s| {
+                s.s_hash == hash
+                    && match (self.name_of(s), name) {
+                        (Some(ours), Some(name)) => ours == name,
+                        _ => true,
+                    }
+            })
+        })?;
+        self.sym_table.get(idx)
     }
-    /// allocate module according to the image header, use p_start to indicate the start address of the image
+    /// name of one of our symbols, None if the image only stored its hash
+    fn name_of(&self, symbol: &Symbol) -> Option<&[u8]> {
+        let names = self.names.as_ref()?.get(symbol.name_offset..)?;
+        let len = names.iter().position(|c| *c == 0)?;
+        Some(&names[..len])
+    }
+    /// address other modules use for one of our symbols, functions are reached through the plt
+    fn address_of(&self, symbol: &Symbol) -> usize {
+        if symbol.in_text {
+            symbol.index2
+        } else {
+            self.ptrs.data_begin + symbol.index1
+        }
+    }
+    /// address of an external symbol as defined by the last dependency that has it
+    fn lookup(dependencies: &[&Module], hash: u32, name: Option<&[u8]>) -> Option<usize> {
+        let mut address = None;
+        for dependency in dependencies {
+            if let Some(symbol) = dependency.get_symbol(hash, name) {
+                address = Some(dependency.address_of(symbol));
+            }
+        }
+        address
+    }
+    /// allocate module according to the image header, image is the whole image as embedded in flash
     /// The allocated module will have everything prepared for symbol resolving
-    pub fn allocate(p_start: *const u8) -> Module {
-        let header = unsafe { &*(p_start as *const ModuleHeader) };
+    /// The image is refused if its prefix, crc32 or any of its records doesn't check out,
+    /// or if it isn't signed by a trusted key, see `allocate_with`
+    pub fn allocate(image: &[u8]) -> Result<Module, LoadError> {
+        Self::allocate_with(image, &LoadOptions::default())
+    }
+    /// allocate with the signature of the image checked according to `options`,
+    /// before anything is allocated, and encrypted images decrypted with its key provider
+    pub fn allocate_with(image: &[u8], options: &LoadOptions) -> Result<Module, LoadError> {
+        let location = (image.as_ptr() as usize, image.len());
+        let image = Image::parse(image)?;
+        let signature = signature::check(&image, options.signature_policy, options.trusted_keys)?;
+        let header = &image.header;
         let case_block_size = 60;
         let non_case_block_size = 20;
-        let mut start = HEADER_LEN + p_start as usize;
+        // freed if anything below fails
+        let mut allocations = Allocations(Vec::new());
+        // stored .text and .data, decrypted into a RAM copy if encrypted
+        let l_payload = header.l_text_stored + header.l_data_stored;
+        let (stored_text, stored_data, decrypted) = match image.encryption() {
+            Some((encryption, aad)) => {
+                let payload =
+                    unsafe { slice::from_raw_parts_mut(allocations.alloc(l_payload), l_payload) };
+                // .text and .data are one ciphertext, in two records
+                payload[..header.l_text_stored].copy_from_slice(image.text);
+                payload[header.l_text_stored..].copy_from_slice(image.data);
+                encryption::decrypt(encryption, aad, options.key_provider, payload)?;
+                let ptr = payload.as_mut_ptr();
+                let (text, data) = payload.split_at(header.l_text_stored);
+                (text, data, Some(ptr))
+            }
+            None => (image.text, image.data, None),
+        };
+        // .text runs in place from the image, or from RAM if it is stored compressed or encrypted,
+        // or has trampolines for `resolve` to patch
+        let copied = header.n_trampolines > 0 && !image.is_packed();
+        let start = if image.is_compressed() {
+            let text = allocations.alloc(header.l_text);
+            lz4::decompress(stored_text, unsafe {
+                slice::from_raw_parts_mut(text, header.l_text)
+            })?;
+            text as usize
+        } else if copied {
+            let text = allocations.alloc(header.l_text);
+            unsafe { slice::from_raw_parts_mut(text, header.l_text) }.copy_from_slice(stored_text);
+            text as usize
+        } else {
+            // decrypted .text is at the start of the RAM copy, which stays allocated
+            stored_text.as_ptr() as usize
+        };
+        if image.is_packed() || copied {
+            // the code just written must be visible to instruction fetch
+            asm::dsb();
+            asm::isb();
+        }
+        // GOT, .data and .bss keep their link-time layout, so that the R9-relative
+        // offsets lld put into movw/movt pairs are valid at runtime
+        let l_static = header.l_got + header.l_data + header.l_bss;
+        let static_base = allocations.alloc_or_dangling(l_static) as usize;
+        let sizes = Sizes::of(&image);
+        let l_plt = sizes.n_plt * (case_block_size + non_case_block_size);
+        let plt_begin = allocations.alloc_or_dangling(l_plt) as usize;
 
         let ptrs = ModulePtr {
-            got_begin: malloc(header.n_reloc * 4, 4) as usize,
-            plt_begin: malloc(header.n_funcs * (case_block_size + non_case_block_size), 4) as usize,
-            data_begin: malloc(header.l_data, 4) as usize,
+            got_begin: static_base,
+            plt_begin,
+            data_begin: static_base + header.l_got,
             text_begin: start,
             text_end: start + header.l_text,
         };
 
-        start += header.l_text;
-        let data = acquire_vec(&mut start, header.l_data);
-        unsafe { slice::from_raw_parts_mut(ptrs.data_begin as *mut u8, header.l_data) }
-            .copy_from_slice(&data);
+        // the GOT is filled by resolve, .data is copied from the image, .bss starts zeroed
+        let statics = unsafe { slice::from_raw_parts_mut(static_base as *mut u8, l_static) };
+        let (got, statics) = statics.split_at_mut(header.l_got);
+        let (data, bss) = statics.split_at_mut(header.l_data);
+        got.fill(0);
+        if image.is_compressed() {
+            lz4::decompress(stored_data, data)?;
+        } else {
+            data.copy_from_slice(stored_data);
+        }
+        bss.fill(0);
+        // unless .text runs from the decrypted copy
+        if let (Some(payload), true) = (decrypted, image.is_compressed()) {
+            allocations.free(payload);
+        }
 
-        let raw_sym_table = acquire_vec(&mut start, header.l_symt);
-        let sym_table = parse_symtable(&header.n_symbol, &raw_sym_table);
+        let sym_table = image.symbols();
+        let symbol_hash = image.symbol_hash().as_bytes().to_vec();
+        let names = image.names().map(|names| names.to_vec());
         unsafe {
             LR_RANGE_TO_BASE.push(Range {
                 start: ptrs.text_begin,
@@ -149,38 +328,61 @@ impl Module {
                 base: ptrs.got_begin,
             });
         }
-        Module { sym_table, ptrs }
+        Ok(Module {
+            sym_table,
+            ptrs,
+            symbol_hash,
+            names,
+            signature,
+            destructors: Vec::new(),
+            allocations: allocations.into_inner(),
+            sizes,
+            image: location,
+        })
+    }
+    /// allocate the module of `image` and resolve it against `dependencies`,
+    /// with the image checked and unpacked according to `options`
+    pub fn load(
+        image: &[u8],
+        dependencies: &[&Module],
+        options: &LoadOptions,
+    ) -> Result<Module, LoadError> {
+        let mut module = Self::allocate_with(image, options)?;
+        if let Err(err) = module.resolve(image, dependencies) {
+            // no constructor has run, so there is nothing to destruct
+            module.release();
+            return Err(err);
+        }
+        Ok(module)
     }
     /// Use the relocation table and function indexes provided by image to resolve symbols references
     /// The dependencies should include all the symbols' definitions
-    pub fn resolve(&mut self, p_start: *const u8, dependencies: Option<Vec<Module>>) {
-        let header: &ModuleHeader = unsafe { &*(p_start as *const ModuleHeader) };
-        let mut start =
-            HEADER_LEN + p_start as usize + header.l_text + header.l_data + header.l_symt;
-
-        let relocs: Vec<_> = acquire_vec(&mut start, header.n_reloc as usize * 8)
-            .chunks(8)
-            .map(|slice| {
-                let offset = usize::from_le_bytes(slice[0..4].try_into().unwrap());
-                let idx = usize::from_le_bytes(slice[4..8].try_into().unwrap());
-                (offset, idx)
-            })
-            .collect();
-        let glb_funcs: Vec<_> = acquire_vec(&mut start, header.n_funcs * 4)
-            .chunks(4)
-            .map(|idx_slice| usize::from_le_bytes(idx_slice.try_into().unwrap()))
-            .collect();
-
-        let allocated_got = unsafe {
-            slice::from_raw_parts_mut(self.ptrs.got_begin as *mut u8, header.n_reloc * 4)
-        };
+    /// image must be the one this module was allocated from, others are refused
+    pub fn resolve(&mut self, image: &[u8], dependencies: &[&Module]) -> Result<(), LoadError> {
+        // its signature was checked by `allocate`, which must have had this very image
+        if (image.as_ptr() as usize, image.len()) != self.image {
+            return Err(LoadError::ImageMismatch);
+        }
+        let mut image = Image::parse(image)?;
+        // everything below is written into the blocks `allocate` sized for its image
+        let sizes = self.sizes;
+        if Sizes::of(&image) != sizes {
+            return Err(LoadError::ImageMismatch);
+        }
+        if image.is_packed() {
+            let text =
+                unsafe { slice::from_raw_parts(self.ptrs.text_begin as *const u8, sizes.l_text) };
+            image = image.unpack(text)?;
+        }
+        let trampolines: Vec<_> = image.trampolines().collect();
+        let plt_funcs = plt_funcs(&image);
 
         // generate plt and copy to RAM
         let case_block_size = 60;
         let non_case_block_size = 20;
         let plt = generate_plt(
             {
-                glb_funcs
+                plt_funcs
                     .iter()
                     .map(|idx| (self.sym_table[*idx].index1 + self.ptrs.text_begin).to_le_bytes())
                     .collect::<Vec<_>>()
@@ -189,68 +391,94 @@ impl Module {
             self.ptrs.got_begin,
         );
         let allocated_plt = self.ptrs.plt_begin as *mut u8;
+        let l_plt = sizes.n_plt * (case_block_size + non_case_block_size);
         unsafe {
-            slice::from_raw_parts_mut(allocated_plt, plt.len()).copy_from_slice(&plt);
+            slice::from_raw_parts_mut(allocated_plt, l_plt).copy_from_slice(&plt);
         }
 
-        let text_begin_ptr = self.ptrs.text_begin as *mut u8;
-        for (offset, symt_idx) in relocs {
-            let sym = &self.sym_table[symt_idx];
-            match sym.s_type & 3 {
-                0 | 1 => {
-                    // Exported / Local
-                    let got_index = usize::from_le_bytes(unsafe {
-                        *text_begin_ptr.offset(offset as isize).cast::<[u8; 4]>()
-                    });
-                    let entry = sym.index1
-                        + if sym.s_type > 4 {
-                            self.ptrs.text_begin
-                        } else {
-                            self.ptrs.data_begin
-                        };
-                    let entry = entry.to_le_bytes();
-                    for j in 0..4 {
-                        allocated_got[got_index + j] = entry[j];
-                    }
-                }
-                2 => {
-                    // External
-                    if let Some(ref dependencies) = dependencies {
-                        for dependency in dependencies {
-                            let symbol = dependency.get_symbol(&sym.s_name);
-                            if let Some(symbol) = symbol {
-                                let entry = symbol.index2.to_le_bytes();
-                                let got_index = usize::from_le_bytes(unsafe {
-                                    *text_begin_ptr.offset(offset as isize).cast::<[u8; 4]>()
-                                });
-                                for j in 0..4 {
-                                    allocated_got[got_index + j] = entry[j];
-                                }
-                            }
-                        }
-                    }
-                }
-                _ => {}
-            }
-        }
-        let plt_1_len = non_case_block_size * header.n_funcs;
-        for (i, idx) in glb_funcs.iter().enumerate() {
+        let bases = Bases {
+            text: self.ptrs.text_begin,
+            data: self.ptrs.data_begin,
+        };
+        let lookup = |sym: &Symbol| Self::lookup(dependencies, sym.s_hash, self.name_of(sym));
+        let allocated_got =
+            unsafe { slice::from_raw_parts_mut(self.ptrs.got_begin as *mut u8, sizes.l_got) };
+        link::fill_got(&image, &self.sym_table, bases, allocated_got, lookup)?;
+        let allocated_data =
+            unsafe { slice::from_raw_parts_mut(self.ptrs.data_begin as *mut u8, sizes.l_data) };
+        link::relocate_data(&image, &self.sym_table, bases, allocated_data, lookup)?;
+
+        let plt_1_len = non_case_block_size * plt_funcs.len();
+        for (i, idx) in plt_funcs.iter().enumerate() {
             self.sym_table[*idx].index1 = allocated_plt as usize + non_case_block_size * i + 1;
             self.sym_table[*idx].index2 =
                 allocated_plt as usize + case_block_size * i + plt_1_len + 1;
         }
+
+        // trampolines switch R9 to our static base themselves, no svc needed,
+        // `Image` checked that each holds the movw/movt pair patched here
+        let text =
+            unsafe { slice::from_raw_parts_mut(self.ptrs.text_begin as *mut u8, sizes.l_text) };
+        for trampoline in &trampolines {
+            let at = trampoline.offset as usize + TRAMPOLINE_STATIC_BASE;
+            text[at..at + 8].copy_from_slice(&instr::ldr(9, self.ptrs.got_begin));
+            let entry = self.ptrs.text_begin + trampoline.offset as usize + 1;
+            let symbol = &mut self.sym_table[trampoline.symbol as usize];
+            symbol.index1 = entry;
+            symbol.index2 = entry;
+        }
+        if !trampolines.is_empty() {
+            // the patched code must be visible to instruction fetch
+            asm::dsb();
+            asm::isb();
+        }
+
+        // constructors run once everything else is in place, with R9 at our static base
+        self.destructors = image
+            .destructors()
+            .map(|offset| self.ptrs.text_begin + offset)
+            .collect();
+        for offset in image.constructors() {
+            unsafe { call_with_static_base(self.ptrs.text_begin + offset, self.ptrs.got_begin) };
+        }
+        Ok(())
+    }
+    /// Run the destructors of the module and give back the memory `allocate` took for it.
+    /// Modules resolved against it may not be used afterwards,
+    /// and the cases the svc handler added to its plt stay allocated
+    pub fn unload(self) {
+        for destructor in &self.destructors {
+            unsafe { call_with_static_base(*destructor, self.ptrs.got_begin) };
+        }
+        self.release();
+    }
+    /// forget the address range of the module and give back its memory
+    fn release(self) {
+        unsafe {
+            LR_RANGE_TO_BASE.retain(|range| {
+                range.start != self.ptrs.text_begin || range.base != self.ptrs.got_begin
+            });
+        }
+        for (ptr, len) in self.allocations {
+            free(ptr as *mut u8, len, 4);
+        }
     }
     pub fn entry_by_name(&self, name: &str) -> usize {
-        self.get_symbol(name).expect("Symbol not found").index1
+        self.get_symbol(gnu_hash(name.as_bytes()), Some(name.as_bytes()))
+            .expect("Symbol not found")
+            .index1
     }
-    /// Given symbol name (whose type is T) and the module it belongs to
+    /// Given symbol name (whose type is T, living in .data or .bss) and the module it belongs to
     /// given function to convert little-endian bytes to T
     /// return a copy to the symbol
     pub fn val_by_name<T, F>(&self, name: &str, bytes_to_t: F) -> T
     where
         F: Fn(&[u8]) -> T,
     {
-        let offset = self.get_symbol(name).expect("Symbol not found").index1;
+        let offset = self
+            .get_symbol(gnu_hash(name.as_bytes()), Some(name.as_bytes()))
+            .expect("Symbol not found")
+            .index1;
         let size_of = mem::size_of::<T>();
         unsafe {
             let data_begin = self.ptrs.data_begin as *const u8;
@@ -0,0 +1,58 @@
+//! Ed25519 signature check of module images, done before anything is allocated or resolved
+//!
+//! Like `image.rs` this only depends on `core` and the image, so it builds on the host.
+use ed25519_dalek::{Signature, VerifyingKey};
+use image_format::PUBLIC_KEY_LEN;
+
+use super::image::{Image, LoadError};
+
+/// Public keys images may be signed with, as written by `build_script --public-key`,
+/// e.g. `*include_bytes!("../../keys/release.pub")`
+pub const TRUSTED_KEYS: &[[u8; PUBLIC_KEY_LEN]] = &[];
+
+/// What to do with an image that isn't signed by a trusted key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignaturePolicy {
+    /// refuse it
+    #[default]
+    Require,
+    /// load it anyway, the module records why it wasn't trusted
+    WarnOnly,
+}
+
+/// Outcome of the signature check of a loaded module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// signed by one of the trusted keys
+    Trusted,
+    /// loaded under `SignaturePolicy::WarnOnly` although the check failed for this reason
+    Unverified(LoadError),
+}
+
+/// check the signature record of `image` against the trusted keys
+fn verify(image: &Image, trusted_keys: &[[u8; PUBLIC_KEY_LEN]]) -> Result<(), LoadError> {
+    let signature = image.signature().ok_or(LoadError::Unsigned)?;
+    if !trusted_keys.contains(&signature.public_key) {
+        return Err(LoadError::UntrustedKey);
+    }
+    let key =
+        VerifyingKey::from_bytes(&signature.public_key).map_err(|_| LoadError::UntrustedKey)?;
+    key.verify_strict(
+        image.signed_bytes(),
+        &Signature::from_bytes(&signature.signature),
+    )
+    .map_err(|_| LoadError::BadSignature)
+}
+
+/// Apply `policy` to `image`, an image that fails the check is refused under `Require`
+pub fn check(
+    image: &Image,
+    policy: SignaturePolicy,
+    trusted_keys: &[[u8; PUBLIC_KEY_LEN]],
+) -> Result<SignatureStatus, LoadError> {
+    match (verify(image, trusted_keys), policy) {
+        (Ok(()), _) => Ok(SignatureStatus::Trusted),
+        (Err(err), SignaturePolicy::Require) => Err(err),
+        (Err(err), SignaturePolicy::WarnOnly) => Ok(SignatureStatus::Unverified(err)),
+    }
+}
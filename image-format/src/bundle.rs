@@ -0,0 +1,250 @@
+//! Bundles: several module images and an index over them, shipped as one blob
+//!
+//! Layout, all numbers little-endian u32:
+//!     magic, version, number of modules, index length, bundle length, crc32 of the index
+//!     index: one `BundleEntry` per module, then the NUL-terminated names and versions
+//!     images, each starting at a multiple of `BUNDLE_ALIGN` from the start of the bundle
+//!
+//! The crc32 in the prefix covers the index, the one in each entry covers its image,
+//! so a module is checked when it is taken out of the bundle, not when the bundle is opened.
+use alloc::vec::Vec;
+
+use crate::entries::{Entry, Table};
+use crate::record::read_u32;
+use crate::{crc, FormatError, BUNDLE_ALIGN, BUNDLE_MAGIC, BUNDLE_PREFIX_LEN, BUNDLE_VERSION};
+
+/// An entry of the bundle index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleEntry {
+    /// where the module name starts among the names following the entries
+    pub name_offset: u32,
+    /// where the module version starts among the names
+    pub version_offset: u32,
+    /// where the image starts, from the start of the bundle
+    pub offset: u32,
+    pub len: u32,
+    /// crc32 of the image
+    pub crc32: u32,
+}
+
+impl Entry for BundleEntry {
+    const LEN: usize = 5 * 4;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for word in [
+            self.name_offset,
+            self.version_offset,
+            self.offset,
+            self.len,
+            self.crc32,
+        ] {
+            out.extend(word.to_le_bytes());
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<BundleEntry> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let word = |i: usize| read_u32(bytes, i * 4);
+        Some(BundleEntry {
+            name_offset: word(0),
+            version_offset: word(1),
+            offset: word(2),
+            len: word(3),
+            crc32: word(4),
+        })
+    }
+}
+
+/// A module of a bundle, as listed by its index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleModule<'a> {
+    pub name: &'a [u8],
+    pub version: &'a [u8],
+    /// the image, not checked against `crc32` yet
+    pub image: &'a [u8],
+    pub crc32: u32,
+}
+
+impl<'a> BundleModule<'a> {
+    /// the image, if it matches the crc32 in the index
+    pub fn check(&self) -> Result<&'a [u8], FormatError> {
+        let actual = crc::crc32(self.image);
+        if actual != self.crc32 {
+            return Err(FormatError::Corrupted {
+                expected: self.crc32,
+                actual,
+            });
+        }
+        Ok(self.image)
+    }
+}
+
+/// The NUL-terminated string at `offset`, without the NUL
+fn string_at(names: &[u8], offset: u32) -> Option<&[u8]> {
+    let names = names.get(offset as usize..)?;
+    let len = names.iter().position(|c| *c == 0)?;
+    Some(&names[..len])
+}
+
+/// A bundle whose prefix and index have been checked
+#[derive(Debug, Clone, Copy)]
+pub struct Bundle<'a> {
+    bytes: &'a [u8],
+    entries: Table<'a, BundleEntry>,
+    names: &'a [u8],
+}
+
+impl<'a> Bundle<'a> {
+    /// Check magic, version, lengths and crc32 of the index of `bundle`, and that every
+    /// entry has a name and version and an image within the bundle
+    pub fn decode(bundle: &'a [u8]) -> Result<Bundle<'a>, FormatError> {
+        if bundle.len() < BUNDLE_PREFIX_LEN {
+            return Err(FormatError::Truncated {
+                expected: BUNDLE_PREFIX_LEN,
+                actual: bundle.len(),
+            });
+        }
+        let word = |i: usize| read_u32(bundle, i * 4);
+        let (magic, version, n_modules, l_index, l_bundle, crc32) =
+            (word(0), word(1), word(2), word(3), word(4), word(5));
+        if magic != BUNDLE_MAGIC {
+            return Err(FormatError::BadMagic(magic));
+        }
+        if version != BUNDLE_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        let l_bundle = l_bundle as usize;
+        if l_bundle < BUNDLE_PREFIX_LEN || bundle.len() < l_bundle {
+            return Err(FormatError::Truncated {
+                expected: l_bundle,
+                actual: bundle.len(),
+            });
+        }
+        let bytes = &bundle[..l_bundle];
+        let index = bytes
+            .get(BUNDLE_PREFIX_LEN..BUNDLE_PREFIX_LEN.saturating_add(l_index as usize))
+            .ok_or(FormatError::BadLayout)?;
+        let actual = crc::crc32(index);
+        if actual != crc32 {
+            return Err(FormatError::Corrupted {
+                expected: crc32,
+                actual,
+            });
+        }
+        let l_entries = (n_modules as usize)
+            .checked_mul(BundleEntry::LEN)
+            .ok_or(FormatError::BadLayout)?;
+        let (entries, names) = index
+            .split_at_checked(l_entries)
+            .ok_or(FormatError::BadLayout)?;
+        let bundle = Bundle {
+            bytes,
+            entries: Table::new(entries).ok_or(FormatError::BadLayout)?,
+            names,
+        };
+        let images_at = BUNDLE_PREFIX_LEN + index.len();
+        for (i, entry) in bundle.entries.iter().enumerate() {
+            let valid = entry.is_some_and(|entry| {
+                let start = entry.offset as usize;
+                string_at(names, entry.name_offset).is_some()
+                    && string_at(names, entry.version_offset).is_some()
+                    && start >= images_at
+                    && start
+                        .checked_add(entry.len as usize)
+                        .is_some_and(|end| end <= l_bundle)
+            });
+            if !valid {
+                return Err(FormatError::BadIndexEntry(i));
+            }
+        }
+        Ok(bundle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// module `i` in index order
+    pub fn get(&self, i: usize) -> Option<BundleModule<'a>> {
+        let entry = self.entries.get(i)?;
+        let start = entry.offset as usize;
+        Some(BundleModule {
+            name: string_at(self.names, entry.name_offset)?,
+            version: string_at(self.names, entry.version_offset)?,
+            image: self.bytes.get(start..start + entry.len as usize)?,
+            crc32: entry.crc32,
+        })
+    }
+
+    /// every module in index order
+    pub fn iter(&self) -> impl Iterator<Item = BundleModule<'a>> + '_ {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+
+    /// the first module named `name`
+    pub fn find(&self, name: &str) -> Option<BundleModule<'a>> {
+        self.iter().find(|module| module.name == name.as_bytes())
+    }
+}
+
+/// Encode a bundle of `modules`, triples of name, version and image.
+/// Names and versions must not contain NUL.
+pub fn encode_bundle(modules: &[(&str, &str, &[u8])]) -> Vec<u8> {
+    let mut names: Vec<u8> = Vec::new();
+    let mut push_name = |name: &str| {
+        assert!(
+            !name.contains('\0'),
+            "`{}` can't be stored in a bundle",
+            name
+        );
+        let at = names.len() as u32;
+        names.extend(name.as_bytes());
+        names.push(0);
+        at
+    };
+    let offsets: Vec<(u32, u32)> = modules
+        .iter()
+        .map(|(name, version, _)| (push_name(name), push_name(version)))
+        .collect();
+    let l_index = modules.len() * BundleEntry::LEN + names.len();
+
+    let images_at = (BUNDLE_PREFIX_LEN + l_index).next_multiple_of(BUNDLE_ALIGN);
+    let mut images: Vec<u8> = Vec::new();
+    let mut index: Vec<u8> = Vec::with_capacity(l_index);
+    for ((_, _, image), (name_offset, version_offset)) in modules.iter().zip(offsets) {
+        BundleEntry {
+            name_offset,
+            version_offset,
+            offset: (images_at + images.len()) as u32,
+            len: image.len() as u32,
+            crc32: crc::crc32(image),
+        }
+        .encode(&mut index);
+        images.extend(*image);
+        images.resize(images.len().next_multiple_of(BUNDLE_ALIGN), 0);
+    }
+    index.extend(&names);
+
+    let l_bundle = images_at + images.len();
+    let mut bundle: Vec<u8> = Vec::with_capacity(l_bundle);
+    for word in [
+        BUNDLE_MAGIC,
+        BUNDLE_VERSION,
+        modules.len() as u32,
+        l_index as u32,
+        l_bundle as u32,
+        crc::crc32(&index),
+    ] {
+        bundle.extend(word.to_le_bytes());
+    }
+    bundle.extend(&index);
+    bundle.resize(images_at, 0);
+    bundle.extend(&images);
+    bundle
+}
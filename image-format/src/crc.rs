@@ -0,0 +1,13 @@
+/// CRC-32 (IEEE 802.3, reflected, poly 0xEDB88320), same as zlib/crc32fast
+/// bitwise to keep flash usage down, images are only checked once at load time
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
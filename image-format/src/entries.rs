@@ -0,0 +1,492 @@
+//! Structures stored in records, and the tables made of them
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::hash::gnu_hash;
+use crate::record::read_u32;
+use crate::{PUBLIC_KEY_LEN, SIGNATURE_LEN};
+
+/// A fixed-size structure stored in a record, on its own or as an entry of a table
+pub trait Entry: Sized {
+    /// encoded size in bytes
+    const LEN: usize;
+    /// append the encoding of `self` to `out`
+    fn encode(&self, out: &mut Vec<u8>);
+    /// None unless `bytes` are LEN bytes encoding a valid value
+    fn decode(bytes: &[u8]) -> Option<Self>;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::LEN);
+        self.encode(&mut out);
+        out
+    }
+}
+
+/// Encode `entries` one after the other, as a table record holds them
+pub fn encode_table<T: Entry>(entries: &[T]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(entries.len() * T::LEN);
+    for entry in entries {
+        entry.encode(&mut out);
+    }
+    out
+}
+
+/// A table as stored in a record, entries are decoded on access
+pub struct Table<'a, T> {
+    bytes: &'a [u8],
+    entry: PhantomData<T>,
+}
+
+impl<'a, T: Entry + 'a> Table<'a, T> {
+    /// None unless `bytes` hold a whole number of entries
+    pub fn new(bytes: &'a [u8]) -> Option<Table<'a, T>> {
+        bytes.len().is_multiple_of(T::LEN).then_some(Table {
+            bytes,
+            entry: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() / T::LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// entry `i`, None if it is out of range or not a valid entry
+    pub fn get(&self, i: usize) -> Option<T> {
+        let at = i.checked_mul(T::LEN)?;
+        T::decode(self.bytes.get(at..at.checked_add(T::LEN)?)?)
+    }
+
+    /// every entry in order, None for the ones that aren't valid
+    pub fn iter(&self) -> impl Iterator<Item = Option<T>> + 'a {
+        self.bytes.chunks_exact(T::LEN).map(T::decode)
+    }
+
+    /// the table as stored in the image
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<T> Clone for Table<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Table<'_, T> {}
+
+impl<T> Default for Table<'_, T> {
+    fn default() -> Self {
+        Table {
+            bytes: &[],
+            entry: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Table<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("bytes", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// A symbol table index, e.g. of a global function
+impl Entry for u32 {
+    const LEN: usize = 4;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<u32> {
+        (bytes.len() == Self::LEN).then(|| read_u32(bytes, 0))
+    }
+}
+
+/// Sizes in bytes of what dl-lib allocates, and the `IMAGE_FLAG_*` flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub text: u32,
+    pub data: u32,
+    pub bss: u32,
+    /// R9 points at the GOT, .data and .bss follow it
+    pub got: u32,
+    pub flags: u32,
+}
+
+impl Entry for Layout {
+    const LEN: usize = 5 * 4;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for word in [self.text, self.data, self.bss, self.got, self.flags] {
+            out.extend(word.to_le_bytes());
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Layout> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let word = |i: usize| read_u32(bytes, i * 4);
+        Some(Layout {
+            text: word(0),
+            data: word(1),
+            bss: word(2),
+            got: word(3),
+            flags: word(4),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// defined and only used within the module, has no name
+    Local,
+    /// defined and visible to other modules
+    Exported,
+    /// defined by another module
+    External,
+}
+
+/// symbol type lives in the top bits of the first word of a symbol entry
+const SYM_TYPE_SHIFT: u32 = 28;
+/// set in the symbol type if the symbol lives in .text rather than .data
+const SYM_IN_TEXT: u32 = 4;
+/// the name offset takes the bits below the type
+const NAME_OFFSET_MASK: u32 = (1 << SYM_TYPE_SHIFT) - 1;
+
+/// An entry of the symbol table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolEntry {
+    pub kind: SymbolKind,
+    /// the address is an offset into .text rather than .data (.bss following .data)
+    pub in_text: bool,
+    /// where the name starts in the flat names
+    pub name_offset: u32,
+    /// offset into its section, 0 for external symbols
+    pub address: u32,
+}
+
+impl Entry for SymbolEntry {
+    const LEN: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        assert!(
+            self.name_offset <= NAME_OFFSET_MASK,
+            "symbol names too long for the image format"
+        );
+        let kind = match self.kind {
+            SymbolKind::Local => 0,
+            SymbolKind::Exported => 1,
+            SymbolKind::External => 2,
+        };
+        let s_type = kind | if self.in_text { SYM_IN_TEXT } else { 0 };
+        out.extend((s_type << SYM_TYPE_SHIFT | self.name_offset).to_le_bytes());
+        out.extend(self.address.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<SymbolEntry> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let word = read_u32(bytes, 0);
+        let s_type = word >> SYM_TYPE_SHIFT;
+        let kind = match s_type & !SYM_IN_TEXT {
+            0 => SymbolKind::Local,
+            1 => SymbolKind::Exported,
+            2 => SymbolKind::External,
+            _ => return None,
+        };
+        Some(SymbolEntry {
+            kind,
+            in_text: s_type & SYM_IN_TEXT != 0,
+            name_offset: word & NAME_OFFSET_MASK,
+            address: read_u32(bytes, 4),
+        })
+    }
+}
+
+/// How a symbol's name is stored among the flat names: its hash, or the name and a NUL
+pub fn name_bytes(name: &str, hashed_names: bool) -> Vec<u8> {
+    if hashed_names {
+        gnu_hash(name.as_bytes()).to_le_bytes().to_vec()
+    } else {
+        let mut bytes = Vec::with_capacity(name.len() + 1);
+        bytes.extend(name.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+}
+
+/// The symbol table record: number of symbols, the entries, then the flat names
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Symbols<'a> {
+    pub entries: Table<'a, SymbolEntry>,
+    pub names: &'a [u8],
+}
+
+impl<'a> Symbols<'a> {
+    pub fn encode(entries: &[SymbolEntry], names: &[u8]) -> Vec<u8> {
+        let mut out = (entries.len() as u32).to_le_bytes().to_vec();
+        out.extend(encode_table(entries));
+        out.extend(names);
+        out
+    }
+
+    /// None if the record is too short for the number of symbols it claims
+    pub fn decode(bytes: &'a [u8]) -> Option<Symbols<'a>> {
+        let n_symbol = read_u32(bytes.get(..4)?, 0) as usize;
+        let (entries, names) = bytes[4..].split_at_checked(n_symbol.checked_mul(8)?)?;
+        Some(Symbols {
+            entries: Table::new(entries)?,
+            names,
+        })
+    }
+
+    /// the NUL-terminated name at `name_offset`, without the NUL
+    pub fn name(&self, name_offset: u32) -> Option<&'a [u8]> {
+        let names = self.names.get(name_offset as usize..)?;
+        let len = names.iter().position(|c| *c == 0)?;
+        Some(&names[..len])
+    }
+
+    /// hash of the name at `name_offset`, either stored as is or of a NUL-terminated name
+    pub fn name_hash(&self, name_offset: u32, hashed_names: bool) -> Option<u32> {
+        if hashed_names {
+            let at = name_offset as usize;
+            return self
+                .names
+                .get(at..at.checked_add(4)?)
+                .map(|hash| read_u32(hash, 0));
+        }
+        self.name(name_offset).map(gnu_hash)
+    }
+}
+
+/// A GOT relocation: the word at `offset` in .text holds the GOT slot (in bytes)
+/// that must receive the address of symbol `symbol`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GotReloc {
+    pub offset: u32,
+    pub symbol: u32,
+}
+
+impl Entry for GotReloc {
+    const LEN: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.offset.to_le_bytes());
+        out.extend(self.symbol.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<GotReloc> {
+        (bytes.len() == Self::LEN).then(|| GotReloc {
+            offset: read_u32(bytes, 0),
+            symbol: read_u32(bytes, 4),
+        })
+    }
+}
+
+/// An exported function entered through a trampoline in .text, which switches R9 to the
+/// module's static base around the call: the trampoline starts at `offset` in .text,
+/// and `symbol` is the symbol table index of the function, one of the exports
+///
+/// ```text
+/// push.w  {r9, lr}
+/// movw    r9, #0      @ offset + TRAMPOLINE_STATIC_BASE, patched by the loader
+/// movt    r9, #0
+/// bl      func
+/// pop.w   {r9, pc}
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trampoline {
+    pub offset: u32,
+    pub symbol: u32,
+}
+
+impl Entry for Trampoline {
+    const LEN: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.offset.to_le_bytes());
+        out.extend(self.symbol.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Trampoline> {
+        (bytes.len() == Self::LEN).then(|| Trampoline {
+            offset: read_u32(bytes, 0),
+            symbol: read_u32(bytes, 4),
+        })
+    }
+}
+
+/// What the word of a data relocation is relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocTarget {
+    /// offset into .text
+    Text,
+    /// offset into .data, .bss included
+    Data,
+    /// addend to the address of an external symbol, by symbol table index
+    Symbol(u32),
+}
+
+/// kind of a data relocation target, stored in the top bits of its second word
+const DATA_RELOC_KIND_SHIFT: u32 = 28;
+const DATA_RELOC_INDEX_MASK: u32 = (1 << DATA_RELOC_KIND_SHIFT) - 1;
+const DATA_RELOC_TEXT: u32 = 0;
+const DATA_RELOC_DATA: u32 = 1;
+const DATA_RELOC_SYMBOL: u32 = 2;
+
+/// A pointer stored in .data: the word at `offset` in .data must be relocated against `target`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataReloc {
+    pub offset: u32,
+    pub target: RelocTarget,
+}
+
+impl Entry for DataReloc {
+    const LEN: usize = 8;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (kind, index) = match self.target {
+            RelocTarget::Text => (DATA_RELOC_TEXT, 0),
+            RelocTarget::Data => (DATA_RELOC_DATA, 0),
+            RelocTarget::Symbol(index) => {
+                assert!(
+                    index <= DATA_RELOC_INDEX_MASK,
+                    "too many symbols for the image format"
+                );
+                (DATA_RELOC_SYMBOL, index)
+            }
+        };
+        out.extend(self.offset.to_le_bytes());
+        out.extend((kind << DATA_RELOC_KIND_SHIFT | index).to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<DataReloc> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let word = read_u32(bytes, 4);
+        let index = word & DATA_RELOC_INDEX_MASK;
+        let target = match word >> DATA_RELOC_KIND_SHIFT {
+            DATA_RELOC_TEXT if index == 0 => RelocTarget::Text,
+            DATA_RELOC_DATA if index == 0 => RelocTarget::Data,
+            DATA_RELOC_SYMBOL => RelocTarget::Symbol(index),
+            _ => return None,
+        };
+        Some(DataReloc {
+            offset: read_u32(bytes, 0),
+            target,
+        })
+    }
+}
+
+/// How .text and .data of an encrypted image are to be decrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encryption {
+    /// the key the firmware's key provider knows under this id
+    pub key_id: u32,
+    pub nonce: [u8; 12],
+    pub tag: [u8; 16],
+}
+
+impl Entry for Encryption {
+    const LEN: usize = 4 + 12 + 16;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.key_id.to_le_bytes());
+        out.extend(self.nonce);
+        out.extend(self.tag);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Encryption> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        Some(Encryption {
+            key_id: read_u32(bytes, 0),
+            nonce: bytes[4..16].try_into().unwrap(),
+            tag: bytes[16..32].try_into().unwrap(),
+        })
+    }
+}
+
+/// Ed25519 public key and signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl Entry for Signature {
+    const LEN: usize = PUBLIC_KEY_LEN + SIGNATURE_LEN;
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.public_key);
+        out.extend(self.signature);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Signature> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let (public_key, signature) = bytes.split_at(PUBLIC_KEY_LEN);
+        Some(Signature {
+            public_key: public_key.try_into().unwrap(),
+            signature: signature.try_into().unwrap(),
+        })
+    }
+}
+
+/// The metadata record: NUL-terminated `key=value` strings, e.g. `name=<module>`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Metadata<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Metadata<'a> {
+    pub fn new(bytes: &'a [u8]) -> Metadata<'a> {
+        Metadata { bytes }
+    }
+
+    /// keys must not contain `=`, neither keys nor values NUL
+    pub fn encode(pairs: &[(&str, &str)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (key, value) in pairs {
+            assert!(
+                !key.contains(['=', '\0']) && !value.contains('\0'),
+                "metadata `{}` can't be stored",
+                key
+            );
+            out.extend(key.as_bytes());
+            out.push(b'=');
+            out.extend(value.as_bytes());
+            out.push(0);
+        }
+        out
+    }
+
+    /// every key and value in order, strings without `=` are skipped
+    pub fn iter(&self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        self.bytes.split(|c| *c == 0).filter_map(|entry| {
+            let split = entry.iter().position(|c| *c == b'=')?;
+            Some((&entry[..split], &entry[split + 1..]))
+        })
+    }
+
+    /// value of the first entry named `key`
+    pub fn get(&self, key: &str) -> Option<&'a [u8]> {
+        self.iter()
+            .find(|(name, _)| *name == key.as_bytes())
+            .map(|(_, value)| value)
+    }
+}
@@ -0,0 +1,167 @@
+//! Hash table over the exported symbols, so that dl-lib finds a symbol by name in O(1)
+//!
+//! Layout, all words little-endian u32:
+//!     n_buckets, bloom_words, bloom_shift, n_entries
+//!     bloom filter, bloom_words words
+//!     buckets, n_buckets words: index of the bucket's first entry, or EMPTY_BUCKET
+//!     entries, n_entries pairs: symbol hash (low bit set on a bucket's last entry), symbol index
+//!
+//! Entries are grouped by bucket, like the chains of a GNU hash section.
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::record::read_u32;
+
+/// bucket without any symbol
+const EMPTY_BUCKET: u32 = u32::MAX;
+/// second bloom filter bit is taken from the hash shifted right by this many bits
+const BLOOM_SHIFT: u32 = 6;
+/// bloom filter bits reserved per symbol
+const BLOOM_BITS_PER_SYMBOL: usize = 12;
+
+/// djb2 hash as used by GNU hash sections, symbols are identified by the hash of their name
+pub fn gnu_hash(name: &[u8]) -> u32 {
+    name.iter()
+        .fold(5381u32, |h, c| h.wrapping_mul(33).wrapping_add(*c as u32))
+}
+
+/// Build the table for `symbols`, pairs of symbol table index and name
+pub fn hash_table(symbols: &[(u32, &str)]) -> Vec<u8> {
+    let n_buckets = symbols.len().max(1);
+    let bloom_words = (symbols.len() * BLOOM_BITS_PER_SYMBOL)
+        .div_ceil(32)
+        .next_power_of_two();
+
+    let mut bloom = vec![0u32; bloom_words];
+    let mut entries: Vec<(usize, u32, u32)> = symbols
+        .iter()
+        .map(|(idx, name)| {
+            let hash = gnu_hash(name.as_bytes());
+            let word = (hash / 32) as usize % bloom_words;
+            bloom[word] |= 1 << (hash % 32) | 1 << ((hash >> BLOOM_SHIFT) % 32);
+            (hash as usize % n_buckets, hash, *idx)
+        })
+        .collect();
+    // grouped by bucket, in symbol table order within a bucket
+    entries.sort_by_key(|(bucket, _, idx)| (*bucket, *idx));
+
+    let mut buckets = vec![EMPTY_BUCKET; n_buckets];
+    for (i, (bucket, _, _)) in entries.iter().enumerate().rev() {
+        buckets[*bucket] = i as u32;
+    }
+
+    let mut table: Vec<u8> = Vec::new();
+    for word in [
+        n_buckets as u32,
+        bloom_words as u32,
+        BLOOM_SHIFT,
+        entries.len() as u32,
+    ] {
+        table.extend(word.to_le_bytes());
+    }
+    for word in bloom.iter().chain(&buckets) {
+        table.extend(word.to_le_bytes());
+    }
+    for (i, (bucket, hash, idx)) in entries.iter().enumerate() {
+        let last = entries.get(i + 1).is_none_or(|next| next.0 != *bucket);
+        let hash = (hash & !1) | last as u32;
+        table.extend(hash.to_le_bytes());
+        table.extend(idx.to_le_bytes());
+    }
+    table
+}
+
+/// Hash table over the exported symbols of an image, as built by `hash_table`
+///
+/// Lookups never index out of the table, even if it wasn't checked by `decode`.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolHash<'a> {
+    table: &'a [u8],
+}
+
+impl<'a> SymbolHash<'a> {
+    pub fn new(table: &'a [u8]) -> SymbolHash<'a> {
+        SymbolHash { table }
+    }
+
+    /// None unless the sizes in the table add up to its length
+    /// and every bucket leads to an entry
+    pub fn decode(table: &'a [u8]) -> Option<SymbolHash<'a>> {
+        let hash = SymbolHash { table };
+        let (n_buckets, bloom_words, bloom_shift, n_entries) = hash.sizes();
+        let len = [n_buckets, bloom_words, n_entries, n_entries]
+            .iter()
+            .try_fold(16usize, |len, words| len.checked_add(words.checked_mul(4)?))?;
+        if table.len() < 16
+            || len != table.len()
+            || n_buckets == 0
+            || !bloom_words.is_power_of_two()
+            || bloom_shift >= 32
+        {
+            return None;
+        }
+        (0..n_buckets)
+            .map(|bucket| hash.word(4 + bloom_words + bucket).unwrap())
+            .all(|first| first == EMPTY_BUCKET || (first as usize) < n_entries)
+            .then_some(hash)
+    }
+
+    /// the table as stored in the image
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.table
+    }
+
+    fn word(&self, i: usize) -> Option<u32> {
+        let at = i.checked_mul(4)?;
+        self.table
+            .get(at..at.checked_add(4)?)
+            .map(|word| read_u32(word, 0))
+    }
+
+    fn sizes(&self) -> (usize, usize, u32, usize) {
+        let word = |i| self.word(i).unwrap_or(0);
+        (
+            word(0) as usize,
+            word(1) as usize,
+            word(2),
+            word(3) as usize,
+        )
+    }
+
+    fn entry(&self, i: usize) -> Option<(u32, usize)> {
+        let (n_buckets, bloom_words, _, _) = self.sizes();
+        let at = 4 + bloom_words + n_buckets + 2 * i;
+        Some((self.word(at)?, self.word(at + 1)? as usize))
+    }
+
+    /// every entry: hash with the low bit marking the end of a bucket, symbol index
+    pub fn entries(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        let (_, _, _, n_entries) = self.sizes();
+        (0..n_entries).map_while(|i| self.entry(i))
+    }
+
+    /// index of the exported symbol whose name hashes to `hash`,
+    /// `matches` checks a candidate by symbol index
+    pub fn lookup(&self, hash: u32, matches: impl Fn(usize) -> bool) -> Option<usize> {
+        let (n_buckets, bloom_words, bloom_shift, _) = self.sizes();
+        if n_buckets == 0 || bloom_words == 0 {
+            return None;
+        }
+        let bloom = self.word(4 + (hash / 32) as usize % bloom_words)?;
+        let mask = 1 << (hash % 32) | 1 << (hash.checked_shr(bloom_shift).unwrap_or(0) % 32);
+        if bloom & mask != mask {
+            return None;
+        }
+        let mut i = self.word(4 + bloom_words + hash as usize % n_buckets)? as usize;
+        loop {
+            let (entry_hash, symbol) = self.entry(i)?;
+            if entry_hash | 1 == hash | 1 && matches(symbol) {
+                return Some(symbol);
+            }
+            if entry_hash & 1 != 0 {
+                return None;
+            }
+            i += 1;
+        }
+    }
+}
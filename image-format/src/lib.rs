@@ -0,0 +1,125 @@
+//! Module image format, written by build_script and read by dl-lib.
+//!
+//! An image is a prefix (magic, version, arch, image length, crc32 of everything after the
+//! crc field) followed by records, all numbers little-endian u32:
+//!     tag, payload length in bytes, payload, zero padding up to a multiple of 4
+//!
+//! Every tag appears at most once. A loader skips records it doesn't know unless their
+//! tag has `RECORD_REQUIRED` set, so optional information can be added to the format
+//! without bumping `IMAGE_VERSION`.
+//!
+//! Every structure stored in a record can be encoded the way dl-lib reads it and decoded
+//! without trusting the input. Decoding only needs `core`, encoding appends to a `Vec`.
+//!
+//! Several images can be shipped as one bundle with an index over them, see `Bundle`.
+#![no_std]
+extern crate alloc;
+
+mod bundle;
+pub mod crc;
+mod entries;
+mod hash;
+mod record;
+
+pub use bundle::{encode_bundle, Bundle, BundleEntry, BundleModule};
+pub use entries::{
+    encode_table, name_bytes, DataReloc, Encryption, Entry, GotReloc, Layout, Metadata,
+    RelocTarget, Signature, SymbolEntry, SymbolKind, Symbols, Table, Trampoline,
+};
+pub use hash::{gnu_hash, hash_table, SymbolHash};
+pub use record::{check_embedded, encode_image, push_record, record_len, Prefix, Record, Records};
+
+/// "CDLM" read as a little-endian word
+pub const IMAGE_MAGIC: u32 = 0x4d4c_4443;
+/// Bumped whenever the prefix or the meaning of a known record changes
+pub const IMAGE_VERSION: u32 = 8;
+/// thumbv7em, ropi-rwpi with R9 as static base
+pub const IMAGE_ARCH: u32 = 1;
+/// magic, version, arch, image length and crc32, the crc covers everything after it
+pub const IMAGE_PREFIX_LEN: usize = 20;
+/// tag and payload length in front of every record
+pub const RECORD_HEADER_LEN: usize = 8;
+
+/// set in the tag of records a loader must understand to load the image correctly
+pub const RECORD_REQUIRED: u32 = 1 << 31;
+
+/// `Layout`
+pub const RECORD_LAYOUT: u32 = RECORD_REQUIRED | 1;
+/// .text, LZ4-compressed if IMAGE_FLAG_COMPRESSED
+pub const RECORD_TEXT: u32 = RECORD_REQUIRED | 2;
+/// .data, LZ4-compressed if IMAGE_FLAG_COMPRESSED
+pub const RECORD_DATA: u32 = RECORD_REQUIRED | 3;
+/// `Symbols`
+pub const RECORD_SYMBOLS: u32 = RECORD_REQUIRED | 4;
+/// table of `GotReloc`
+pub const RECORD_RELOCS: u32 = RECORD_REQUIRED | 5;
+/// table of u32, the symbol index of every global function, each gets a PLT entry
+pub const RECORD_EXPORTS: u32 = RECORD_REQUIRED | 6;
+/// table of `DataReloc`
+pub const RECORD_DATA_RELOCS: u32 = RECORD_REQUIRED | 7;
+/// `SymbolHash` over the exported symbols
+pub const RECORD_SYMBOL_HASH: u32 = RECORD_REQUIRED | 8;
+/// `Encryption`, .text and .data are AES-256-GCM encrypted as one,
+/// authenticating the layout record
+pub const RECORD_ENCRYPTION: u32 = RECORD_REQUIRED | 9;
+/// `Metadata`
+pub const RECORD_METADATA: u32 = 10;
+/// free-form debugging aid, never read by the loader
+pub const RECORD_DEBUG: u32 = 11;
+/// `Signature` of every byte between the prefix and this record, which must be the last one
+pub const RECORD_SIGNATURE: u32 = 12;
+/// table of `Trampoline`, exported functions entered through them instead of a PLT entry
+pub const RECORD_TRAMPOLINES: u32 = RECORD_REQUIRED | 13;
+/// table of u32, .text offsets (thumb bit set) of the constructors, in the order they run,
+/// with R9 at the static base once the module is resolved
+pub const RECORD_INIT_ARRAY: u32 = RECORD_REQUIRED | 14;
+/// table of u32, .text offsets (thumb bit set) of the destructors, in the order they run
+/// when the module is unloaded, the reverse of .fini_array
+pub const RECORD_FINI_ARRAY: u32 = RECORD_REQUIRED | 15;
+
+/// symbols are named by the hash of their name, no names are stored
+pub const IMAGE_FLAG_HASHED_NAMES: u32 = 1;
+/// .text and .data are stored as LZ4 blocks
+pub const IMAGE_FLAG_COMPRESSED: u32 = 2;
+
+/// size in bytes of a trampoline in .text
+pub const TRAMPOLINE_LEN: usize = 20;
+/// offset in a trampoline of the movw/movt pair loading R9
+pub const TRAMPOLINE_STATIC_BASE: usize = 4;
+/// that pair as linked, `movw r9, #0` and `movt r9, #0`, until the loader patches it
+pub const TRAMPOLINE_UNPATCHED: [u8; 8] = [0x40, 0xf2, 0x00, 0x09, 0xc0, 0xf2, 0x00, 0x09];
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+/// "CDLB" read as a little-endian word
+pub const BUNDLE_MAGIC: u32 = 0x424c_4443;
+/// Bumped whenever the bundle prefix or index changes
+pub const BUNDLE_VERSION: u32 = 1;
+/// magic, version, number of modules, index length, bundle length and crc32 of the index
+pub const BUNDLE_PREFIX_LEN: usize = 24;
+/// images in a bundle start at multiples of this, from the start of the bundle
+pub const BUNDLE_ALIGN: usize = 4;
+
+/// Ways an image or a bundle fails to decode before its records are looked at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// fewer bytes than the prefix, or than the image length recorded in it
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    ArchMismatch(u32),
+    /// crc32 of the records differs from the one in the prefix
+    Corrupted {
+        expected: u32,
+        actual: u32,
+    },
+    /// a record whose payload runs past the end of the image, or a bundle index
+    /// that runs past the end of the bundle
+    BadLayout,
+    /// a bundle index entry whose name, version or image lies outside the bundle
+    BadIndexEntry(usize),
+}
@@ -0,0 +1,224 @@
+//! The image prefix and the records following it
+use alloc::vec::Vec;
+
+use crate::RECORD_METADATA;
+use crate::{crc, FormatError};
+use crate::{IMAGE_ARCH, IMAGE_MAGIC, IMAGE_PREFIX_LEN, IMAGE_VERSION, RECORD_HEADER_LEN};
+
+pub(crate) const fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// The words in front of the records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    pub magic: u32,
+    pub version: u32,
+    pub arch: u32,
+    pub l_image: u32,
+    pub crc32: u32,
+}
+
+impl Prefix {
+    /// Check magic, version, arch tag, length and crc32 of `image`,
+    /// and return its prefix and the records following it
+    pub fn decode(image: &[u8]) -> Result<(Prefix, &[u8]), FormatError> {
+        if image.len() < IMAGE_PREFIX_LEN {
+            return Err(FormatError::Truncated {
+                expected: IMAGE_PREFIX_LEN,
+                actual: image.len(),
+            });
+        }
+        let word = |i: usize| read_u32(image, i * 4);
+        let prefix = Prefix {
+            magic: word(0),
+            version: word(1),
+            arch: word(2),
+            l_image: word(3),
+            crc32: word(4),
+        };
+        if prefix.magic != IMAGE_MAGIC {
+            return Err(FormatError::BadMagic(prefix.magic));
+        }
+        if prefix.version != IMAGE_VERSION {
+            return Err(FormatError::UnsupportedVersion(prefix.version));
+        }
+        if prefix.arch != IMAGE_ARCH {
+            return Err(FormatError::ArchMismatch(prefix.arch));
+        }
+        let l_image = prefix.l_image as usize;
+        if l_image < IMAGE_PREFIX_LEN || image.len() < l_image {
+            return Err(FormatError::Truncated {
+                expected: l_image,
+                actual: image.len(),
+            });
+        }
+        let records = &image[IMAGE_PREFIX_LEN..l_image];
+        let actual = crc::crc32(records);
+        if actual != prefix.crc32 {
+            return Err(FormatError::Corrupted {
+                expected: prefix.crc32,
+                actual,
+            });
+        }
+        Ok((prefix, records))
+    }
+}
+
+/// Check what can be checked of `image` in a const context: magic, version, arch tag,
+/// that the image length is that of `image`, and that its metadata names module `name`.
+/// Firmware embedding an image checks it at compile time this way, the crc32 and the
+/// records are left to the loader.
+pub const fn check_embedded(image: &[u8], name: &str) -> Result<(), &'static str> {
+    if image.len() < IMAGE_PREFIX_LEN {
+        return Err("module image is shorter than its prefix");
+    }
+    if read_u32(image, 0) != IMAGE_MAGIC {
+        return Err("not a module image, bad magic");
+    }
+    if read_u32(image, 4) != IMAGE_VERSION {
+        return Err("module image has a different format version than the loader");
+    }
+    if read_u32(image, 8) != IMAGE_ARCH {
+        return Err("module image is built for a different arch");
+    }
+    if read_u32(image, 12) as usize != image.len() {
+        return Err("module image length doesn't match its file");
+    }
+    let mut at = IMAGE_PREFIX_LEN;
+    while at + RECORD_HEADER_LEN <= image.len() {
+        let tag = read_u32(image, at);
+        let len = read_u32(image, at + 4) as usize;
+        let payload = at + RECORD_HEADER_LEN;
+        if len > image.len() - payload {
+            return Err("module image has a record running past its end");
+        }
+        if tag == RECORD_METADATA {
+            return if names_module(image, payload, payload + len, name.as_bytes()) {
+                Ok(())
+            } else {
+                Err("module image is of a different module")
+            };
+        }
+        at = payload + record_len(len) - RECORD_HEADER_LEN;
+    }
+    Err("module image has no metadata record")
+}
+
+/// whether the first `name=` entry of the metadata in `image[start..end]` is `name=<name>`
+const fn names_module(image: &[u8], start: usize, end: usize, name: &[u8]) -> bool {
+    const KEY: &[u8] = b"name=";
+    let mut entry = start;
+    while entry < end {
+        // the entry runs up to the next NUL
+        let mut entry_end = entry;
+        while entry_end < end && image[entry_end] != 0 {
+            entry_end += 1;
+        }
+        if starts_with(image, entry, entry_end, KEY) {
+            let value = entry + KEY.len();
+            return entry_end - value == name.len() && starts_with(image, value, entry_end, name);
+        }
+        entry = entry_end + 1;
+    }
+    false
+}
+
+/// whether `image[start..end]` starts with `prefix`
+const fn starts_with(image: &[u8], start: usize, end: usize, prefix: &[u8]) -> bool {
+    if end - start < prefix.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < prefix.len() {
+        if image[start + i] != prefix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Prefix `records` with magic, version, arch tag, total length and crc32,
+/// so that dl-lib can refuse truncated, corrupted or stale images
+pub fn encode_image(records: &[u8]) -> Vec<u8> {
+    let l_image = (IMAGE_PREFIX_LEN + records.len()) as u32;
+    let mut image: Vec<u8> = Vec::with_capacity(l_image as usize);
+    for word in [
+        IMAGE_MAGIC,
+        IMAGE_VERSION,
+        IMAGE_ARCH,
+        l_image,
+        crc::crc32(records),
+    ] {
+        image.extend(word.to_le_bytes());
+    }
+    image.extend(records);
+    image
+}
+
+/// length of a record with a payload of `len` bytes, header and padding included
+pub const fn record_len(len: usize) -> usize {
+    RECORD_HEADER_LEN + len.next_multiple_of(4)
+}
+
+/// Append a record to `records`: tag, payload length, payload padded to a multiple of 4
+pub fn push_record(records: &mut Vec<u8>, tag: u32, payload: &[u8]) {
+    records.extend(tag.to_le_bytes());
+    records.extend((payload.len() as u32).to_le_bytes());
+    records.extend(payload);
+    // records start word aligned
+    records.resize(records.len().next_multiple_of(4), 0);
+}
+
+/// One record of an image
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub tag: u32,
+    /// where the record starts, relative to the end of the prefix
+    pub offset: usize,
+    pub payload: &'a [u8],
+}
+
+/// Iterator over `records`, the image between prefix and image length
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+    records: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Records<'a> {
+    pub fn new(records: &'a [u8]) -> Records<'a> {
+        Records { records, pos: 0 }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, FormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.pos;
+        let rest = self.records.get(offset..).filter(|rest| !rest.is_empty())?;
+        let word = |at: usize| rest.get(at..at + 4).map(|word| read_u32(word, 0));
+        let record = word(0).zip(word(4)).and_then(|(tag, len)| {
+            let len = len as usize;
+            let payload = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN.checked_add(len)?)?;
+            Some((tag, payload, record_len(len).min(rest.len())))
+        });
+        match record {
+            Some((tag, payload, skip)) => {
+                self.pos += skip;
+                Some(Ok(Record {
+                    tag,
+                    offset,
+                    payload,
+                }))
+            }
+            None => {
+                // nothing sensible follows a broken record
+                self.pos = self.records.len();
+                Some(Err(FormatError::BadLayout))
+            }
+        }
+    }
+}
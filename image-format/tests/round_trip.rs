@@ -0,0 +1,206 @@
+//! Everything build_script encodes decodes to the same values in dl-lib,
+//! and decoding arbitrary bytes never panics
+use image_format::*;
+use proptest::prelude::*;
+
+fn symbol_kind() -> impl Strategy<Value = SymbolKind> {
+    prop_oneof![
+        Just(SymbolKind::Local),
+        Just(SymbolKind::Exported),
+        Just(SymbolKind::External),
+    ]
+}
+
+fn symbol_entry() -> impl Strategy<Value = SymbolEntry> {
+    (symbol_kind(), any::<bool>(), 0u32..1 << 28, any::<u32>()).prop_map(
+        |(kind, in_text, name_offset, address)| SymbolEntry {
+            kind,
+            in_text,
+            name_offset,
+            address,
+        },
+    )
+}
+
+fn data_reloc() -> impl Strategy<Value = DataReloc> {
+    let target = prop_oneof![
+        Just(RelocTarget::Text),
+        Just(RelocTarget::Data),
+        (0u32..1 << 28).prop_map(RelocTarget::Symbol),
+    ];
+    (any::<u32>(), target).prop_map(|(offset, target)| DataReloc { offset, target })
+}
+
+fn round_trip<T: Entry + PartialEq + std::fmt::Debug>(value: T) {
+    let bytes = value.to_bytes();
+    assert_eq!(bytes.len(), T::LEN);
+    assert_eq!(T::decode(&bytes), Some(value));
+}
+
+proptest! {
+    #[test]
+    fn layout(text: u32, data: u32, bss: u32, got: u32, flags: u32) {
+        round_trip(Layout { text, data, bss, got, flags });
+    }
+
+    #[test]
+    fn symbol_entries(entry in symbol_entry()) {
+        round_trip(entry);
+    }
+
+    #[test]
+    fn got_relocs(offset: u32, symbol: u32) {
+        round_trip(GotReloc { offset, symbol });
+    }
+
+    #[test]
+    fn trampolines(offset: u32, symbol: u32) {
+        round_trip(Trampoline { offset, symbol });
+    }
+
+    #[test]
+    fn data_relocs(reloc in data_reloc()) {
+        round_trip(reloc);
+    }
+
+    #[test]
+    fn encryption(key_id: u32, nonce: [u8; 12], tag: [u8; 16]) {
+        round_trip(Encryption { key_id, nonce, tag });
+    }
+
+    #[test]
+    fn signature(public_key: [u8; 32], signature in prop::collection::vec(any::<u8>(), 64)) {
+        let signature = signature.try_into().unwrap();
+        round_trip(Signature { public_key, signature });
+    }
+
+    #[test]
+    fn tables(relocs in prop::collection::vec(data_reloc(), 0..64)) {
+        let bytes = encode_table(&relocs);
+        let table = Table::<DataReloc>::new(&bytes).unwrap();
+        prop_assert_eq!(table.len(), relocs.len());
+        prop_assert_eq!(table.iter().collect::<Option<Vec<_>>>(), Some(relocs));
+    }
+
+    #[test]
+    fn symbol_tables(
+        symbols in prop::collection::vec((symbol_kind(), any::<bool>(), any::<u32>(), "[a-zA-Z_][a-zA-Z0-9_]{0,20}"), 0..32),
+        hashed_names: bool,
+    ) {
+        let mut names = Vec::new();
+        let entries: Vec<_> = symbols
+            .iter()
+            .map(|(kind, in_text, address, name)| {
+                let name_offset = names.len() as u32;
+                names.extend(name_bytes(name, hashed_names));
+                SymbolEntry { kind: *kind, in_text: *in_text, name_offset, address: *address }
+            })
+            .collect();
+        let bytes = Symbols::encode(&entries, &names);
+        let decoded = Symbols::decode(&bytes).unwrap();
+        prop_assert_eq!(decoded.entries.iter().collect::<Option<Vec<_>>>(), Some(entries.clone()));
+        for (entry, (_, _, _, name)) in entries.iter().zip(&symbols) {
+            prop_assert_eq!(
+                decoded.name_hash(entry.name_offset, hashed_names),
+                Some(gnu_hash(name.as_bytes()))
+            );
+            if !hashed_names {
+                prop_assert_eq!(decoded.name(entry.name_offset), Some(name.as_bytes()));
+            }
+        }
+    }
+
+    #[test]
+    fn metadata(pairs in prop::collection::vec(("[a-z_]{1,8}", "[ -~]{0,16}"), 0..8)) {
+        let refs: Vec<_> = pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let bytes = Metadata::encode(&refs);
+        let metadata = Metadata::new(&bytes);
+        let decoded: Vec<_> = metadata.iter().collect();
+        let expected: Vec<_> = pairs.iter().map(|(k, v)| (k.as_bytes(), v.as_bytes())).collect();
+        prop_assert_eq!(decoded, expected);
+        // the first entry of a key wins
+        for (key, _) in &pairs {
+            let first = pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_bytes());
+            prop_assert_eq!(metadata.get(key), first);
+        }
+    }
+
+    #[test]
+    fn images(records in prop::collection::vec((any::<u32>(), prop::collection::vec(any::<u8>(), 0..64)), 0..16)) {
+        let mut body = Vec::new();
+        for (tag, payload) in &records {
+            push_record(&mut body, *tag, payload);
+        }
+        let image = encode_image(&body);
+        let (prefix, decoded) = Prefix::decode(&image).unwrap();
+        prop_assert_eq!(prefix.l_image as usize, image.len());
+        prop_assert_eq!(decoded, &body[..]);
+        let decoded: Vec<_> = Records::new(decoded)
+            .map(|record| record.map(|record| (record.tag, record.payload.to_vec())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        prop_assert_eq!(decoded, records);
+    }
+
+    #[test]
+    fn embedded_images(name in "[a-z_]{1,12}", other in "[a-z_]{1,12}", payload in prop::collection::vec(any::<u8>(), 0..32)) {
+        let mut body = Vec::new();
+        push_record(&mut body, RECORD_DEBUG, &payload);
+        push_record(&mut body, RECORD_METADATA, &Metadata::encode(&[("version", "1"), ("name", &name)]));
+        let image = encode_image(&body);
+        prop_assert_eq!(check_embedded(&image, &name), Ok(()));
+        prop_assert_eq!(check_embedded(&image, &other).is_ok(), name == other);
+        prop_assert!(check_embedded(&image[..image.len() - 4], &name).is_err());
+    }
+
+    #[test]
+    fn bundle_entries(name_offset: u32, version_offset: u32, offset: u32, len: u32, crc32: u32) {
+        round_trip(BundleEntry { name_offset, version_offset, offset, len, crc32 });
+    }
+
+    #[test]
+    fn bundles(modules in prop::collection::vec(("[a-z_]{1,12}", "[0-9.]{0,8}", prop::collection::vec(any::<u8>(), 0..64)), 0..8)) {
+        let refs: Vec<_> = modules.iter().map(|(n, v, i)| (n.as_str(), v.as_str(), i.as_slice())).collect();
+        let bytes = encode_bundle(&refs);
+        let bundle = Bundle::decode(&bytes).unwrap();
+        prop_assert_eq!(bundle.len(), modules.len());
+        for (module, (name, version, image)) in bundle.iter().zip(&modules) {
+            prop_assert_eq!(module.name, name.as_bytes());
+            prop_assert_eq!(module.version, version.as_bytes());
+            prop_assert_eq!(module.check(), Ok(image.as_slice()));
+            prop_assert_eq!(module.image.as_ptr() as usize % BUNDLE_ALIGN, bytes.as_ptr() as usize % BUNDLE_ALIGN);
+        }
+        // the first module of a name wins
+        for (name, _, _) in &modules {
+            let first = modules.iter().find(|(n, _, _)| n == name).map(|(_, _, i)| i.as_slice());
+            prop_assert_eq!(bundle.find(name).map(|module| module.image), first);
+        }
+    }
+
+    #[test]
+    fn hash_tables(names in prop::collection::btree_set("[a-zA-Z_][a-zA-Z0-9_]{0,12}", 0..64)) {
+        let symbols: Vec<_> = names.iter().enumerate().map(|(i, name)| (i as u32 * 3, name.as_str())).collect();
+        let bytes = hash_table(&symbols);
+        let hash = SymbolHash::decode(&bytes).unwrap();
+        prop_assert_eq!(hash.entries().count(), symbols.len());
+        // names may collide, the loader tells them apart by comparing more than the hash
+        for (idx, name) in &symbols {
+            let found = hash.lookup(gnu_hash(name.as_bytes()), |i| symbols[i / 3].1 == *name);
+            prop_assert_eq!(found, Some(*idx as usize));
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+        let _ = Prefix::decode(&bytes);
+        let _ = Records::new(&bytes).count();
+        let _ = Symbols::decode(&bytes).map(|symbols| symbols.entries.iter().count());
+        let _ = Metadata::new(&bytes).iter().count();
+        let hash = SymbolHash::new(&bytes);
+        let _ = hash.entries().count();
+        let _ = hash.lookup(gnu_hash(&bytes), |_| true);
+        let _ = SymbolHash::decode(&bytes);
+        let _ = check_embedded(&bytes, "module");
+        let _ = Bundle::decode(&bytes).map(|bundle| bundle.iter().filter(|module| module.check().is_ok()).count());
+    }
+}
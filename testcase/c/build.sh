@@ -1,2 +1,2 @@
 arm-none-eabi-gcc -fPIE -msingle-pic-base -mcpu=cortex-m4 -mthumb -fomit-frame-pointer -fno-inline -fno-section-anchors -mno-pic-data-is-text-relative -mlong-calls -O2 -c $1.c
-cp $1.o ../../build_script/module.o
\ No newline at end of file
+(cd ../../build_script && cargo run -- ../testcase/c/$1.o -o ../dl-lib/$1.bin)
\ No newline at end of file
@@ -0,0 +1,13 @@
+int STATE = 0;
+int ORDER = 0;
+
+__attribute__((constructor(101))) static void first(void) { ORDER = ORDER * 10 + 1; }
+
+__attribute__((constructor)) static void init(void) {
+  ORDER = ORDER * 10 + 2;
+  STATE = 42;
+}
+
+__attribute__((destructor)) static void fini(void) { STATE = 0; }
+
+int test(int x) { return STATE + x; }
@@ -1,6 +1,3 @@
-use std::env::join_paths;
-use std::str;
-
 use clap::Parser;
 
 #[derive(Parser, Default, Debug)]
@@ -18,10 +15,12 @@ fn main() {
         .map(|r| r.unwrap())
         .collect();
     paths.sort_by_key(|r| r.path());
+    // testcases whose two builds differ, they fail the run
+    let mut not_reproducible = Vec::new();
 
     for case in paths {
         let path = case.path();
-        let name = (&path).file_name().unwrap().to_str().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
         if args.casename != "all" && name != args.casename {
             continue;
         }
@@ -76,25 +75,48 @@ fn main() {
             println!("\t multiple/none object file.");
             continue;
         }
-        let o3 = std::process::Command::new("bash")
+        let o4 = std::process::Command::new("bash")
+            .current_dir("../build_script")
             .arg("-c")
             .arg(format!(
-                "cp {}  ../build_script/module.o",
-                object[0].path().to_str().unwrap()
+                "cargo run -- {} -n {} -o ../dl-lib/{}.bin",
+                object[0].path().to_str().unwrap(),
+                name,
+                name
             ))
             .output()
             .unwrap();
-        let o4 = std::process::Command::new("bash")
+        if o4.status.success() {
+            println!("\tdl ok.");
+            println!("\t{:}", String::from_utf8(o4.stderr.clone()).unwrap());         
+        } else {
+            println!("\tdl failed.");
+            continue;
+        }
+
+        // images are signed and cached by hash, the same object must give the same bytes
+        let rebuilt = format!("{}/target/{}.bin", path_str, name);
+        let o5 = std::process::Command::new("bash")
             .current_dir("../build_script")
             .arg("-c")
-            .arg("cargo run")
+            .arg(format!(
+                "cargo run -- {} -n {} -o {}",
+                object[0].path().to_str().unwrap(),
+                name,
+                rebuilt
+            ))
             .output()
             .unwrap();
-        if o3.status.success() && o4.status.success() {
-            println!("\tdl ok.");
-            println!("\t{:}", String::from_utf8(o4.stderr.clone()).unwrap());         
+        let first = std::fs::read(format!("../dl-lib/{}.bin", name)).unwrap();
+        if o5.status.success() && std::fs::read(&rebuilt).unwrap() == first {
+            println!("\treproducible.");
         } else {
-            println!("\tdl failed.");
+            println!("\tnot reproducible.");
+            not_reproducible.push(String::from(name));
         }
     }
+    if !not_reproducible.is_empty() {
+        eprintln!("not reproducible: {}", not_reproducible.join(", "));
+        std::process::exit(1);
+    }
 }